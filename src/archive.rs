@@ -0,0 +1,242 @@
+use anyhow::{anyhow, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use tar::{Archive, EntryType};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Archive formats accepted by `--output-format`
+pub const FORMATS: &[&str] = &["tar", "tar.gz", "tar.zst", "zip"];
+
+/// Format is the encoding used when writing the copied content to a single
+/// archive file instead of unpacking it onto the local filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Tar,
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+impl Format {
+    /// Infers the archive format from the extension of the provided path
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Format::TarZst)
+        } else if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tar" => Ok(Format::Tar),
+            "tar.gz" | "tgz" => Ok(Format::TarGz),
+            "tar.zst" | "tzst" => Ok(Format::TarZst),
+            "zip" => Ok(Format::Zip),
+            _ => Err(anyhow!("unsupported archive format {}", s)),
+        }
+    }
+}
+
+/// Output is an archive file the copied content should be written to
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub path: PathBuf,
+    pub format: Format,
+}
+
+/// Re-encodes the tar stream returned by the container runtime into the
+/// requested output archive. Nothing is unpacked onto the local filesystem.
+///
+/// # Arguments
+///
+/// * `bytes` - The tar archive copied out of the container
+/// * `output` - Where and in which format the archive should be written
+pub fn write(bytes: &[u8], output: &Output) -> Result<()> {
+    let file = File::create(&output.path)?;
+    match output.format {
+        Format::Tar => {
+            let mut file = file;
+            file.write_all(bytes)?;
+        }
+        Format::TarGz => {
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            encoder.write_all(bytes)?;
+            encoder.finish()?;
+        }
+        Format::TarZst => {
+            let mut encoder = zstd::Encoder::new(file, 0)?;
+            encoder.write_all(bytes)?;
+            encoder.finish()?;
+        }
+        Format::Zip => write_zip(bytes, file)?,
+    }
+
+    Ok(())
+}
+
+// write_zip walks the entries of the tar archive and adds each of them to a
+// zip archive, keeping the unix permissions of the original entries. Zip
+// archives have no hard links, they get a copy of the content they link to.
+fn write_zip(bytes: &[u8], file: File) -> Result<()> {
+    let mut zip = ZipWriter::new(file);
+    let mut archive = Archive::new(bytes);
+    // Position and size of the content of the files written so far
+    let mut files: HashMap<PathBuf, (usize, usize)> = HashMap::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .unix_permissions(entry.header().mode()?);
+
+        match entry.header().entry_type() {
+            EntryType::Directory => zip.add_directory(path, options)?,
+            EntryType::Regular | EntryType::Continuous => {
+                let start = entry.raw_file_position() as usize;
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content)?;
+                files.insert(normalize(&path), (start, content.len()));
+                zip.start_file(path, options)?;
+                zip.write_all(&content)?;
+            }
+            EntryType::Link => {
+                let target = match entry.link_name()? {
+                    Some(target) => target.to_string_lossy().to_string(),
+                    None => return Err(anyhow!("hard link {} has no target", path)),
+                };
+                let (start, size) = match files.get(&normalize(&target)) {
+                    Some(content) => *content,
+                    None => {
+                        return Err(anyhow!(
+                            "hard link {} points to {} which is not in the archive",
+                            path,
+                            target
+                        ))
+                    }
+                };
+                zip.start_file(path, options)?;
+                zip.write_all(&bytes[start..start + size])?;
+            }
+            EntryType::Symlink => {
+                let target = match entry.link_name()? {
+                    Some(target) => target.to_string_lossy().to_string(),
+                    None => return Err(anyhow!("symlink {} has no target", path)),
+                };
+                zip.add_symlink(path, target, options)?;
+            }
+            other => {
+                warn!(
+                    "⚠️ Skipping {} as {:?} entries cannot be stored in a zip archive",
+                    path, other
+                );
+            }
+        }
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+// normalize returns an archive path without its `.` components, as hard
+// links may name their target with or without a leading `./`
+fn normalize(path: &str) -> PathBuf {
+    Path::new(path)
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write, Format, Output};
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::path::Path;
+
+    #[test]
+    fn test_format_from_path() {
+        let cases = [
+            ("bundle.tar", Some(Format::Tar)),
+            ("out/bundle.tar.gz", Some(Format::TarGz)),
+            ("bundle.tgz", Some(Format::TarGz)),
+            ("bundle.TAR.ZST", Some(Format::TarZst)),
+            ("bundle.zip", Some(Format::Zip)),
+            ("bundle.gz", None),
+            ("bundle", None),
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!(Format::from_path(Path::new(path)), expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_zip_hard_link() {
+        let dir = std::env::temp_dir().join(format!("dcp-zip-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "manifests/a.yaml", &b"data"[..])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        header.set_mode(0o644);
+        builder
+            .append_link(&mut header, "manifests/b.yaml", "./manifests/a.yaml")
+            .unwrap();
+        let linked = builder.into_inner().unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "manifests/b.yaml", "manifests/missing.yaml")
+            .unwrap();
+        let dangling = builder.into_inner().unwrap();
+
+        let output = Output {
+            path: dir.join("content.zip"),
+            format: Format::Zip,
+        };
+        let written = write(&linked, &output);
+        let mut content = String::new();
+        if written.is_ok() {
+            let mut zip = zip::ZipArchive::new(File::open(&output.path).unwrap()).unwrap();
+            zip.by_name("manifests/b.yaml")
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+        }
+        let missing = write(&dangling, &output);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(written.is_ok(), "{:?}", written);
+        assert_eq!(content, "data");
+        assert!(missing.is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::config::{self, Command, Config};
+use crate::pull;
+
+/// Batch is a list of images to copy in a single run, as read by `dcp batch`
+/// from a YAML file:
+///
+/// ```yaml
+/// jobs:
+///   - image: quay.io/operatorhubio/etcd:v0.9.4
+///     content_paths: [manifests, metadata]
+///     destination: bundles/etcd
+///     options:
+///       on_conflict: error
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Batch {
+    pub jobs: Vec<Job>,
+}
+
+/// Job is a single image of a batch
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub image: String,
+    // Where the content is in the container filesystem. Default "/"
+    #[serde(default = "default_content_paths")]
+    pub content_paths: Vec<String>,
+    // Where the content is saved on the filesystem
+    pub destination: String,
+    #[serde(default)]
+    pub options: JobOptions,
+}
+
+/// JobOptions are the options of a job, named like the command line flags
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobOptions {
+    pub force_pull: bool,
+    // Pull policy, instead of `--pull`
+    pub pull: Option<String>,
+    // Remove the image once every content path is copied, if the job pulled it
+    pub rm_image: bool,
+    pub expect_digest: Option<String>,
+    pub strip_components: usize,
+    pub flatten: bool,
+    pub symlinks: Option<String>,
+    pub on_conflict: Option<String>,
+    pub require_empty: bool,
+    pub clean: bool,
+    // How many times the pulls and copies of the job are retried, instead of `--retries`
+    pub retries: Option<u32>,
+}
+
+fn default_content_paths() -> Vec<String> {
+    vec!["/".to_string()]
+}
+
+impl Batch {
+    /// Reads the batch file at the provided path
+    pub fn read(path: &Path) -> Result<Batch> {
+        let content = fs::read_to_string(path)?;
+        let batch: Batch = serde_yaml::from_str(&content)?;
+        if batch.jobs.is_empty() {
+            return Err(anyhow!("no jobs are listed"));
+        }
+        for job in &batch.jobs {
+            if job.content_paths.is_empty() {
+                return Err(anyhow!("{} has no content paths", job.image));
+            }
+        }
+        Ok(batch)
+    }
+}
+
+impl Job {
+    /// Returns the configurations of the copies the job is made of, one per
+    /// content path, all written to the destination of the job. Settings
+    /// that are not options of the job, such as the socket or credentials,
+    /// are taken from the batch configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - Configuration of the batch
+    pub fn configs(&self, base: &Config) -> Result<Vec<Config>> {
+        let mut cfg = base.clone();
+        cfg.command = Command::Copy;
+        cfg.image = self.image.clone();
+        cfg.download_path = self.destination.clone();
+        cfg.pull = match (&self.options.pull, self.options.force_pull) {
+            (Some(pull), _) => pull.parse()?,
+            (None, true) => pull::Policy::Always,
+            (None, false) => base.pull,
+        };
+        // The job removes the image once all its copies are done
+        cfg.rm_image = false;
+        if let Some(retries) = self.options.retries {
+            cfg.retries = retries;
+        }
+        cfg.expect_digest = match &self.options.expect_digest {
+            Some(digest) => Some(config::parse_digest(digest)?),
+            None => None,
+        };
+
+        let opts = &mut cfg.extract;
+        opts.strip_components = self.options.strip_components;
+        opts.flatten = self.options.flatten;
+        if let Some(symlinks) = &self.options.symlinks {
+            opts.symlinks = symlinks.parse()?;
+        }
+        if let Some(on_conflict) = &self.options.on_conflict {
+            opts.on_conflict = on_conflict.parse()?;
+        }
+        opts.require_empty |= self.options.require_empty;
+        opts.clean |= self.options.clean;
+
+        let mut configs = Vec::new();
+        for (i, content_path) in self.content_paths.iter().enumerate() {
+            let mut cfg = cfg.clone();
+            cfg.content_path = content_path.clone();
+            // Only the first copy finds the destination as it was
+            if i > 0 {
+                cfg.extract.require_empty = false;
+                cfg.extract.clean = false;
+            }
+            configs.push(cfg);
+        }
+        Ok(configs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Batch;
+    use crate::config::{Command, Config};
+    use crate::extract::{self, conflict::Conflict, limits::Limits, symlink::Symlinks};
+    use crate::pull::Policy;
+
+    #[test]
+    fn test_configs() {
+        let batch: Batch = serde_yaml::from_str(
+            r#"
+jobs:
+  - image: quay.io/tflannag/bundles:resolveset-v0.0.2
+    content_paths: [manifests, metadata]
+    destination: bundles/resolveset
+    options:
+      clean: true
+      on_conflict: error
+      pull: never
+      rm_image: true
+      retries: 5
+  - image: quay.io/tyslaton/sample-catalog:v0.0.4
+    destination: catalog
+"#,
+        )
+        .unwrap();
+        let base = Config {
+            command: Command::Batch,
+            extract: extract::Options {
+                symlinks: Symlinks::Skip,
+                limits: Limits {
+                    max_entries: Some(1000),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let configs = batch.jobs[0].configs(&base).unwrap();
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0].command, Command::Copy);
+        assert_eq!(configs[1].content_path, "metadata");
+        assert_eq!(configs[1].download_path, "bundles/resolveset");
+        assert_eq!(configs[1].extract.on_conflict, Conflict::Error);
+        assert!(configs[0].extract.clean && !configs[1].extract.clean);
+        assert_eq!(configs[1].pull, Policy::Never);
+        assert!(!configs[0].rm_image);
+        assert_eq!(configs[1].retries, 5);
+
+        let configs = batch.jobs[1].configs(&base).unwrap();
+        assert_eq!(configs[0].content_path, "/");
+        // the options of the batch apply to the jobs that do not override them
+        assert_eq!(configs[0].extract.symlinks, Symlinks::Skip);
+        assert_eq!(configs[0].extract.limits.max_entries, Some(1000));
+        assert_eq!(configs[0].extract.on_conflict, Conflict::Overwrite);
+
+        assert!(serde_yaml::from_str::<Batch>("jobs:\n  - image: alpine\n").is_err());
+    }
+}
@@ -1,14 +1,72 @@
 use anyhow::{anyhow, Result};
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
 
+use crate::archive::{self, Format, Output};
+use crate::error::Error;
+use crate::extract::checksum::{self, Checksums};
+use crate::extract::layer::Selection;
+use crate::extract::limits::{self, Limits};
+use crate::extract::{self, conflict, metadata, special, symlink};
+use crate::pull;
+use crate::report;
+use crate::retry;
 use crate::runtime;
+use crate::settings::{Registry, Settings};
+use crate::signature;
 
 pub const VERSION: &str = "0.4.1";
 
-#[derive(Debug)]
+// Prefix of the environment variables setting the flags
+const ENV_PREFIX: &str = "DCP_";
+
+// Help listing the environment variables, shown below the flags
+const ENV_HELP: &str = "ENVIRONMENT:
+    Every flag can also be set with a DCP_* environment variable named after its long name, as listed
+    below. Flags without a value take true or false, such as DCP_FORCE_PULL=true. The images and the
+    batch file are arguments, not flags: they are only taken from the command line. Flags given on the
+    command line take precedence over the environment, which takes precedence over the .dcp.toml of the
+    project and the config.toml of the user. A flag set to true in a config file is turned off with its
+    variable, such as DCP_FLATTEN=false.
+
+    Runtime:  DCP_PROFILE, DCP_SOCKET, DCP_USERNAME, DCP_PASSWORD, DCP_PULL, DCP_FORCE_PULL, DCP_RM_IMAGE,
+              DCP_RETRIES, DCP_TIMEOUT, DCP_CONNECT_TIMEOUT, DCP_JOBS
+    Content:  DCP_DOWNLOAD_PATH, DCP_CONTENT_PATH, DCP_WRITE_TO_STDOUT, DCP_OUTPUT, DCP_OUTPUT_FORMAT,
+              DCP_LAYER, DCP_LAYERS, DCP_DRY_RUN
+    Writing:  DCP_STRIP_COMPONENTS, DCP_FLATTEN, DCP_SYMLINKS, DCP_ON_CONFLICT, DCP_REQUIRE_EMPTY, DCP_CLEAN,
+              DCP_PRESERVE_OWNER, DCP_CHOWN, DCP_NO_SAME_PERMISSIONS, DCP_STRIP_SETUID, DCP_PRESERVE_XATTRS,
+              DCP_MTIME, DCP_SPECIAL_FILES
+    Limits:   DCP_MAX_TOTAL_SIZE, DCP_MAX_FILE_SIZE, DCP_MAX_ENTRIES, DCP_MAX_PATH_DEPTH
+    Trust:    DCP_EXPECT_DIGEST, DCP_POLICY, DCP_INSECURE_POLICY, DCP_VERIFY_KEY, DCP_SIGNATURE_LAYOUT
+    Output:   DCP_LOG_LEVEL, DCP_QUIET, DCP_CHECKSUMS, DCP_CHECKSUMS_STYLE, DCP_REPORT, DCP_REPORT_FILE,
+              DCP_JSON, DCP_UNIFIED";
+
+/// Command is what dcp does with the content of the image
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    // Copy the content into the download path
+    #[default]
+    Copy,
+    // Compare the download path with the content
+    Verify,
+    // Print the metadata of the image
+    Inspect,
+    // Compare the content of the image with another image
+    Diff,
+    // Copy the content of every image listed in a batch file
+    Batch,
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct Config {
-    // List of images
+    // What to do with the content of the image
+    pub command: Command,
+    // Image processed by the run
     pub image: String,
+    // Every image provided, copied at the same time when there are several
+    pub images: Vec<String>,
     // Where the download files should be saved on the filesystem. Default "."
     pub download_path: String,
     // Where the content (files) are in the container filesystem. Default "/"
@@ -17,14 +75,54 @@ pub struct Config {
     pub write_to_stdout: bool,
     // What level of logs to output
     pub log_level: String,
+    // Do not display progress bars
+    pub quiet: bool,
     // Username for singing into a private registry
     pub username: String,
     // Password for signing into a private registry
     pub password: String,
-    // Force a pull even if the image is present locally
-    pub force_pull: bool,
+    // When the image is pulled. Default only when missing
+    pub pull: pull::Policy,
+    // Remove the image once copied, if it was pulled by this run
+    pub rm_image: bool,
     // Specify a custom socket to utilize for the runtime
     pub socket: String,
+    // Digest the image must have, such as `sha256:...`
+    pub expect_digest: Option<String>,
+    // Trust policy to evaluate instead of the default containers-policy.json
+    pub policy: Option<PathBuf>,
+    // Do not evaluate any trust policy
+    pub insecure_policy: bool,
+    // Key the image must be signed with, verified before copying content
+    pub signature: Option<signature::Options>,
+    // Options controlling how the copied content is written locally
+    pub extract: extract::Options,
+    // Format of the report written at the end of the run, if any
+    pub report: Option<report::Format>,
+    // Where the report should be written. Default stdout
+    pub report_file: Option<PathBuf>,
+    // Image the content of the image is compared to
+    pub other_image: Option<String>,
+    // Print the output of inspect and diff as JSON instead of text
+    pub json: bool,
+    // Show unified diffs of the small text files that differ
+    pub unified: bool,
+    // Layers to extract instead of the merged filesystem of the image
+    pub layers: Option<Selection>,
+    // Batch file listing the images to copy
+    pub batch_file: Option<PathBuf>,
+    // How many images are processed at the same time
+    pub jobs: usize,
+    // How many times a pull or a copy failing with a transient error is retried
+    pub retries: u32,
+    // How long the whole run may take, if limited
+    pub timeout: Option<Duration>,
+    // How long the runtime has to answer when connecting to its socket
+    pub connect_timeout: Duration,
+    // Settings of the registries read from the config files
+    pub registries: BTreeMap<String, Registry>,
+    // Pull from the registry of the image without verifying its certificates
+    pub insecure: bool,
 }
 
 pub fn get_args() -> Result<Config> {
@@ -32,28 +130,31 @@ pub fn get_args() -> Result<Config> {
         .version(VERSION)
         .author("exdx")
         .about("docker cp made easy")
+        .after_help(ENV_HELP)
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
-            Arg::with_name("image")
-                .value_name("IMAGE")
-                .help("Container image to extract content from")
-                .required(true),
+            Arg::with_name("profile")
+                .value_name("PROFILE")
+                .help("Profile of the config files to apply, such as `ci`")
+                .long("profile")
+                .global(true),
         )
         .arg(
-            Arg::with_name("download-path")
-                .value_name("DOWNLOAD-PATH")
-                .help("Where the image contents should be saved on the filesystem")
-                .default_value(".")
-                .short("d")
-                .long("download-path"),
+            image_arg()
+                .help("Container images to extract content from. Several images are processed at the same time")
+                .multiple(true),
         )
+        .args(&runtime_args())
+        .args(&content_args())
         .arg(
-            Arg::with_name("content-path")
-                .value_name("CONTENT-PATH")
-                .help("Where in the container filesystem the content to extract is")
-                .short("c")
-                .default_value("/")
-                .long("content-path"),
+            Arg::with_name("jobs")
+                .value_name("JOBS")
+                .help("How many images are processed at the same time")
+                .short("j")
+                .long("jobs")
+                .default_value("4"),
         )
+        .arg(rm_image_arg())
         .arg(
             Arg::with_name("write-to-stdout")
                 .value_name("WRITE-TO-STDOUT")
@@ -63,73 +164,790 @@ pub fn get_args() -> Result<Config> {
                 .long("write-to-stdout"),
         )
         .arg(
-            Arg::with_name("username")
-                .value_name("USERNAME")
-                .help("Username used for singing into a private registry.")
-                .short("u")
-                .long("username")
-                .default_value(""),
-
+            Arg::with_name("output")
+                .value_name("OUTPUT")
+                .help("Write the content to a single archive file instead of unpacking it into the download path")
+                .short("o")
+                .long("output"),
         )
         .arg(
-            Arg::with_name("password")
-                .value_name("PASSWORD")
-                .help("Password used for signing into a private registry. * WARNING *: Writing credentials to your terminal is risky. Be sure you are okay with them showing up in your history")
-                .short("p")
-                .long("password")
-                .default_value(""),
-
+            Arg::with_name("output-format")
+                .value_name("OUTPUT-FORMAT")
+                .help("Archive format of the output file. Inferred from the output file extension when not set")
+                .long("output-format")
+                .possible_values(archive::FORMATS)
+                .requires("output"),
+        )
+        .arg(symlinks_arg())
+        .arg(
+            Arg::with_name("on-conflict")
+                .value_name("ON-CONFLICT")
+                .help("What to do with files that already exist in the download path. `backup` renames them with a `~` suffix")
+                .long("on-conflict")
+                .possible_values(conflict::POLICIES)
+                .default_value("overwrite"),
+        )
+        .arg(
+            Arg::with_name("require-empty")
+                .value_name("REQUIRE-EMPTY")
+                .help("Fail if the download path already has content")
+                .takes_value(false)
+                .long("require-empty")
+                .conflicts_with("clean"),
+        )
+        .arg(
+            Arg::with_name("clean")
+                .value_name("CLEAN")
+                .help("Remove the existing content of the download path before copying. Refused for the working and home directories")
+                .takes_value(false)
+                .long("clean"),
+        )
+        .arg(
+            Arg::with_name("preserve-owner")
+                .value_name("PRESERVE-OWNER")
+                .help("Restore the numeric owner of extracted files. Usually requires root")
+                .takes_value(false)
+                .long("preserve-owner")
+                .conflicts_with("chown"),
+        )
+        .arg(
+            Arg::with_name("no-same-permissions")
+                .value_name("NO-SAME-PERMISSIONS")
+                .help("Apply the user's umask to the permissions of extracted files")
+                .takes_value(false)
+                .long("no-same-permissions"),
+        )
+        .arg(
+            Arg::with_name("strip-setuid")
+                .value_name("STRIP-SETUID")
+                .help("Remove the setuid and setgid bits of extracted files")
+                .takes_value(false)
+                .long("strip-setuid"),
+        )
+        .arg(
+            Arg::with_name("preserve-xattrs")
+                .value_name("PRESERVE-XATTRS")
+                .help("Restore the extended attributes of extracted files")
+                .takes_value(false)
+                .long("preserve-xattrs"),
+        )
+        .arg(
+            Arg::with_name("mtime")
+                .value_name("MTIME")
+                .help("Modification time of extracted files. Accepts: [archive, now, <seconds since the unix epoch>]")
+                .long("mtime")
+                .default_value("archive"),
+        )
+        .arg(
+            Arg::with_name("chown")
+                .value_name("UID:GID")
+                .help("Numeric owner of extracted files")
+                .long("chown"),
+        )
+        .arg(special_files_arg())
+        .arg(
+            Arg::with_name("max-total-size")
+                .value_name("SIZE")
+                .help("Abort if the content copied out of the image is larger than this. Accepts bytes or K, M, G and T suffixes")
+                .long("max-total-size"),
+        )
+        .arg(
+            Arg::with_name("max-file-size")
+                .value_name("SIZE")
+                .help("Abort if a single file in the content is larger than this. Accepts bytes or K, M, G and T suffixes")
+                .long("max-file-size"),
+        )
+        .arg(
+            Arg::with_name("max-entries")
+                .value_name("COUNT")
+                .help("Abort if the content has more files, directories and links than this")
+                .long("max-entries"),
         )
         .arg(
-            Arg::with_name("log-level")
-                .value_name("LOG-LEVEL")
-                .help("What level of logs to output. Accepts: [info, debug, trace, error, warn]")
-                .short("l")
-                .long("log-level")
-                .default_value("debug"),
+            Arg::with_name("max-path-depth")
+                .value_name("DEPTH")
+                .help("Abort if a path in the content has more components than this")
+                .long("max-path-depth"),
         )
         .arg(
-            Arg::with_name("force-pull")
-                .value_name("FORCE-PULL")
-                .help("Force a pull even if the image is present locally")
+            Arg::with_name("dry-run")
+                .value_name("DRY-RUN")
+                .help("Report whether the image would be pulled and which files would be written, without writing anything")
                 .takes_value(false)
-                .long("force-pull")
-                .short("f")
-        )
-        .arg(
-            Arg::with_name("socket")
-                .value_name("SOCKET")
-                .help("Specify a custom socket to utilize for the runtime")
-                .long("socket")
-                .short("s")
-                .default_value(runtime::DEFAULT_SOCKET)
-        )
-        .get_matches();
-
-    let image = matches.value_of("image").unwrap().to_string();
-    let download_path = matches.value_of("download-path").unwrap().to_string();
-    let content_path = matches.value_of("content-path").unwrap().to_string();
-    let write_to_stdout = matches.is_present("write-to-stdout");
-    let force_pull = matches.is_present("force-pull");
-    let log_level = matches.value_of("log-level").unwrap().to_string();
-    let socket = matches.value_of("socket").unwrap().to_string();
+                .long("dry-run"),
+        )
+        .arg(
+            Arg::with_name("report")
+                .value_name("REPORT")
+                .help("Write a machine-readable report of the run, listing every file written with its size and sha256")
+                .long("report")
+                .possible_values(report::FORMATS),
+        )
+        .arg(
+            Arg::with_name("report-file")
+                .value_name("REPORT-FILE")
+                .help("Write the report to this file instead of stdout")
+                .long("report-file")
+                .requires("report"),
+        )
+        .arg(
+            Arg::with_name("checksums")
+                .value_name("ALGORITHM")
+                .help("Write a checksum manifest of every extracted file, such as SHA256SUMS, into the download path")
+                .long("checksums")
+                .possible_values(checksum::ALGORITHMS)
+                .conflicts_with("output"),
+        )
+        .arg(
+            Arg::with_name("checksums-style")
+                .value_name("STYLE")
+                .help("Format of the checksum manifest lines, as read by `sha256sum -c` (gnu) or `shasum -c` (bsd)")
+                .long("checksums-style")
+                .possible_values(checksum::STYLES)
+                .default_value("gnu"),
+        )
+        .arg(
+            Arg::with_name("layer")
+                .value_name("LAYER")
+                .help("Only extract what this layer adds, by position starting at 0 for the base layer or by sha256:... digest")
+                .long("layer")
+                .conflicts_with("layers"),
+        )
+        .arg(
+            Arg::with_name("layers")
+                .value_name("RANGE")
+                .help("Only extract what the layers in this range add, such as 2..4, 3.. or ..1. Both ends are included")
+                .long("layers"),
+        )
+        .arg(
+            Arg::with_name("verify-key")
+                .value_name("KEY")
+                .help("Refuse to copy content out of images that are not signed with this cosign public key")
+                .long("verify-key"),
+        )
+        .arg(
+            Arg::with_name("signature-layout")
+                .value_name("DIR")
+                .help("Read the signature of the image from this OCI layout instead of its registry")
+                .long("signature-layout"),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .after_help(ENV_HELP)
+                .about("Report files of the download path that were added, removed or modified compared to the image content")
+                .args(&common_args())
+                .arg(symlinks_arg())
+                .arg(special_files_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("inspect")
+                .after_help(ENV_HELP)
+                .about("Print the labels, environment, entrypoint, creation date, layers and size of the image")
+                .args(&image_args())
+                .arg(expect_digest_arg())
+                .arg(
+                    Arg::with_name("json")
+                        .value_name("JSON")
+                        .help("Print the metadata as JSON")
+                        .takes_value(false)
+                        .long("json"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .after_help(ENV_HELP)
+                .about("Report files that were added, removed or modified between the content of two images")
+                .args(&image_args())
+                .arg(
+                    Arg::with_name("other-image")
+                        .value_name("OTHER-IMAGE")
+                        .help("Container image to compare the content with")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("content-path")
+                        .value_name("CONTENT-PATH")
+                        .help("Where in the container filesystem the content to compare is")
+                        .short("c")
+                        .default_value("/")
+                        .long("content-path"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .value_name("JSON")
+                        .help("Print the differences as JSON")
+                        .takes_value(false)
+                        .long("json"),
+                )
+                .arg(
+                    Arg::with_name("unified")
+                        .value_name("UNIFIED")
+                        .help("Show a unified diff of the small text files that differ, such as YAML manifests")
+                        .takes_value(false)
+                        .long("unified"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("batch")
+                .after_help(ENV_HELP)
+                .about("Copy the content of every image listed in a batch file")
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("FILE")
+                        .help("YAML file listing the images to copy, with their content paths, destination and options")
+                        .required(true),
+                )
+                .args(&runtime_args())
+                .arg(
+                    Arg::with_name("jobs")
+                        .value_name("JOBS")
+                        .help("How many images are processed at the same time")
+                        .short("j")
+                        .long("jobs")
+                        .default_value("4"),
+                )
+                .arg(rm_image_arg()),
+        )
+        .get_matches_safe();
+    let matches = match matches {
+        Ok(matches) => matches,
+        // Help and version are printed the way clap does it
+        Err(e) if !e.use_stderr() => e.exit(),
+        // Usage errors are printed by clap, but exit with the code of dcp
+        Err(e) => {
+            eprintln!("{}", e.message);
+            std::process::exit(Error::Usage(e.message).exit_code())
+        }
+    };
+
+    let (command, matches) = match matches.subcommand() {
+        ("verify", Some(verify)) => (Command::Verify, verify),
+        ("inspect", Some(inspect)) => (Command::Inspect, inspect),
+        ("diff", Some(diff)) => (Command::Diff, diff),
+        ("batch", Some(batch)) => (Command::Batch, batch),
+        _ => (Command::Copy, &matches),
+    };
+
+    let env: BTreeMap<String, String> = std::env::vars()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect();
+    let profile = matches
+        .value_of("profile")
+        .or_else(|| env.get(&env_var("profile")).map(String::as_str));
+    let settings = match Settings::load(profile) {
+        Ok(settings) => settings,
+        Err(e) => return Err(anyhow!("❌ error reading the config files: {}", e)),
+    };
+    let args = Args {
+        matches,
+        env: &env,
+        settings: &settings,
+    };
+
+    // Batches list their images in the batch file
+    let images: Vec<String> = matches
+        .values_of("image")
+        .map(|images| images.map(String::from).collect())
+        .unwrap_or_default();
+    let image = images.first().cloned().unwrap_or_default();
+    // Inspecting an image does not take the content arguments
+    let download_path = args.value_of("download-path").unwrap_or(".").to_string();
+    let content_path = args.value_of("content-path").unwrap_or("/").to_string();
+    let write_to_stdout = args.is_present("write-to-stdout")?;
+    let pull = if args.is_present("force-pull")? {
+        pull::Policy::Always
+    } else {
+        match args.value_of("pull").unwrap_or("missing").parse() {
+            Ok(pull) => pull,
+            Err(e) => return Err(anyhow!("❌ invalid value for --pull: {}", e)),
+        }
+    };
+    let rm_image = args.is_present("rm-image")?;
+    let log_level = args.value_of("log-level").unwrap().to_string();
+    let quiet = args.is_present("quiet")?;
+    let socket = args.value_of("socket").unwrap().to_string();
     // TODO (tyslaton): Need to come up with a way for this to be extracted from the docker config to be more secure locally.
-    let username = matches.value_of("username").unwrap().to_string();
-    let password = matches.value_of("password").unwrap().to_string();
+    let username = args.value_of("username").unwrap().to_string();
+    let password = args.value_of("password").unwrap().to_string();
+
+    let strip_components = match args.value_of("strip-components").unwrap_or("0").parse() {
+        Ok(n) => n,
+        Err(e) => return Err(anyhow!("❌ invalid value for --strip-components: {}", e)),
+    };
+    let flatten = args.is_present("flatten")?;
+    let expect_digest = match args.value_of("expect-digest") {
+        Some(digest) => Some(parse_digest(digest)?),
+        None => None,
+    };
+    let policy = args.value_of("policy").map(PathBuf::from);
+    let insecure_policy = args.is_present("insecure-policy")?;
+    let extract = match command {
+        Command::Copy => extract::Options {
+            strip_components,
+            flatten,
+            ..extract_options(&args)?
+        },
+        // Jobs write their content to their destination, with the options
+        // of the batch unless their own options override them
+        Command::Batch => extract::Options {
+            output: None,
+            ..extract_options(&args)?
+        },
+        // Content is verified against the paths and links it was extracted to
+        Command::Verify => extract::Options {
+            strip_components,
+            flatten,
+            symlinks: parse_option(&args, "symlinks")?,
+            special_files: parse_option(&args, "special-files")?,
+            ..Default::default()
+        },
+        Command::Inspect | Command::Diff => extract::Options {
+            strip_components,
+            flatten,
+            ..Default::default()
+        },
+    };
+    // Archive outputs keep the paths and links of the content as copied
+    if extract.output.is_some() {
+        let ignored: Vec<&str> = [
+            ("--strip-components", extract.strip_components > 0),
+            ("--flatten", extract.flatten),
+            (
+                "--symlinks",
+                extract.symlinks != symlink::Symlinks::Preserve,
+            ),
+            (
+                "--on-conflict",
+                extract.on_conflict != conflict::Conflict::Overwrite,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(flag, set)| set.then_some(flag))
+        .collect();
+        if !ignored.is_empty() {
+            return Err(anyhow!(
+                "❌ {} cannot be used with --output",
+                ignored.join(", ")
+            ));
+        }
+    }
+
+    let layout = args.value_of("signature-layout").map(PathBuf::from);
+    let signature = match (args.value_of("verify-key"), layout) {
+        (Some(key), layout) => Some(signature::Options {
+            key: PathBuf::from(key),
+            layout,
+        }),
+        (None, Some(_)) => return Err(anyhow!("❌ --signature-layout requires --verify-key")),
+        (None, None) => None,
+    };
+    let report = match args.value_of("report") {
+        Some(format) => Some(format.parse()?),
+        None => None,
+    };
+    let report_file = args.value_of("report-file").map(PathBuf::from);
+    let batch_file = args.value_of("file").map(PathBuf::from);
+    let jobs = match args.value_of("jobs").unwrap_or("1").parse() {
+        Ok(jobs) if jobs > 0 => jobs,
+        _ => {
+            return Err(anyhow!(
+                "❌ invalid value for --jobs: expected a positive number"
+            ))
+        }
+    };
+    let retries = match args.value_of("retries").unwrap().parse() {
+        Ok(retries) => retries,
+        Err(e) => return Err(anyhow!("❌ invalid value for --retries: {}", e)),
+    };
+    let timeout = match args.value_of("timeout").map(retry::parse_duration) {
+        Some(Ok(timeout)) => Some(timeout),
+        Some(Err(e)) => return Err(anyhow!("❌ invalid value for --timeout: {}", e)),
+        None => None,
+    };
+    let connect_timeout = match retry::parse_duration(args.value_of("connect-timeout").unwrap()) {
+        Ok(timeout) => timeout,
+        Err(e) => return Err(anyhow!("❌ invalid value for --connect-timeout: {}", e)),
+    };
+    let other_image = args.value_of("other-image").map(String::from);
+    let json = args.is_present("json")?;
+    let unified = args.is_present("unified")?;
+    let layers = match (args.value_of("layer"), args.value_of("layers")) {
+        (Some(layer), _) => match Selection::parse_layer(layer) {
+            Ok(selection) => Some(selection),
+            Err(e) => return Err(anyhow!("❌ invalid value for --layer: {}", e)),
+        },
+        (None, Some(range)) => match Selection::parse_range(range) {
+            Ok(selection) => Some(selection),
+            Err(e) => return Err(anyhow!("❌ invalid value for --layers: {}", e)),
+        },
+        (None, None) => None,
+    };
 
     if write_to_stdout {
         return Err(anyhow!("❌ writing to stdout is not currently implemented"));
     };
 
+    if images.len() > 1 && expect_digest.is_some() {
+        return Err(anyhow!(
+            "❌ --expect-digest cannot be used with several images"
+        ));
+    }
+
     Ok(Config {
+        command,
         image,
+        images,
         download_path,
         content_path,
         write_to_stdout,
         log_level,
+        quiet,
         username,
         password,
-        force_pull,
+        pull,
+        rm_image,
         socket,
+        expect_digest,
+        policy,
+        insecure_policy,
+        signature,
+        extract,
+        report,
+        report_file,
+        other_image,
+        json,
+        unified,
+        layers,
+        batch_file,
+        jobs,
+        retries,
+        timeout,
+        connect_timeout,
+        registries: settings.registries,
+        insecure: false,
     })
 }
+
+/// Returns the environment variable setting the flag with the provided long
+/// name, such as `DCP_DOWNLOAD_PATH` for `download-path`
+pub fn env_var(name: &str) -> String {
+    format!("{}{}", ENV_PREFIX, name.to_uppercase().replace('-', "_"))
+}
+
+// Args reads the arguments of the command line, falling back to the DCP_*
+// environment variables then to the config files for the options it does
+// not set, before the defaults of the flags
+struct Args<'a> {
+    matches: &'a ArgMatches<'a>,
+    // DCP_* environment variables
+    env: &'a BTreeMap<String, String>,
+    settings: &'a Settings,
+}
+
+impl<'a> Args<'a> {
+    fn value_of(&self, name: &str) -> Option<&'a str> {
+        if self.matches.occurrences_of(name) > 0 {
+            return self.matches.value_of(name);
+        }
+        self.env
+            .get(&env_var(name))
+            .map(String::as_str)
+            .or_else(|| self.settings.value_of(name))
+            .or_else(|| self.matches.value_of(name))
+    }
+
+    fn is_present(&self, name: &str) -> Result<bool> {
+        if self.matches.is_present(name) {
+            return Ok(true);
+        }
+        let var = env_var(name);
+        match self
+            .env
+            .get(&var)
+            .map(|value| value.to_lowercase())
+            .as_deref()
+        {
+            Some("1" | "true" | "yes" | "on") => Ok(true),
+            Some("" | "0" | "false" | "no" | "off") => Ok(false),
+            Some(value) => Err(anyhow!(
+                "❌ invalid value for {}: expected true or false, got {}",
+                var,
+                value
+            )),
+            None => Ok(self.settings.is_present(name)),
+        }
+    }
+}
+
+// extract_options parses the arguments controlling how the copied content
+// is written locally
+fn extract_options(args: &Args) -> Result<extract::Options> {
+    let output = match args.value_of("output") {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            let format = match args.value_of("output-format") {
+                Some(format) => format.parse()?,
+                None => match Format::from_path(&path) {
+                    Some(format) => format,
+                    None => {
+                        return Err(anyhow!(
+                            "❌ could not infer the archive format of {}, use --output-format",
+                            path.display()
+                        ))
+                    }
+                },
+            };
+            Some(Output { path, format })
+        }
+        None => None,
+    };
+
+    let symlinks = parse_option(args, "symlinks")?;
+    let on_conflict = parse_option(args, "on-conflict")?;
+    let require_empty = args.is_present("require-empty")?;
+    let clean = args.is_present("clean")?;
+    let preserve_owner = args.is_present("preserve-owner")?;
+    let no_same_permissions = args.is_present("no-same-permissions")?;
+    let strip_setuid = args.is_present("strip-setuid")?;
+    let preserve_xattrs = args.is_present("preserve-xattrs")?;
+    let mtime = parse_option(args, "mtime")?;
+    let special_files = parse_option(args, "special-files")?;
+    let limits = Limits {
+        max_total_size: parse_limit(args, "max-total-size", limits::parse_size)?,
+        max_file_size: parse_limit(args, "max-file-size", limits::parse_size)?,
+        max_entries: parse_limit(args, "max-entries", |v| Ok(v.parse()?))?,
+        max_path_depth: parse_limit(args, "max-path-depth", |v| Ok(v.parse()?))?,
+    };
+    let dry_run = args.is_present("dry-run")?;
+    let chown = match args.value_of("chown") {
+        Some(owner) => Some(metadata::parse_owner(owner)?),
+        None => None,
+    };
+    let checksums = match args.value_of("checksums") {
+        Some(algorithm) => Some(Checksums {
+            algorithm: algorithm.parse()?,
+            style: parse_option(args, "checksums-style")?,
+        }),
+        None => None,
+    };
+
+    Ok(extract::Options {
+        output,
+        symlinks,
+        on_conflict,
+        require_empty,
+        clean,
+        preserve_owner,
+        no_same_permissions,
+        strip_setuid,
+        preserve_xattrs,
+        mtime,
+        chown,
+        special_files,
+        limits,
+        dry_run,
+        checksums,
+        ..Default::default()
+    })
+}
+
+// common_args returns the arguments shared by copying and verifying content
+fn common_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let mut args = image_args();
+    args.extend(content_args());
+    args
+}
+
+// content_args returns the arguments selecting the content of the image and
+// where it is saved
+fn content_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        expect_digest_arg(),
+        Arg::with_name("download-path")
+            .value_name("DOWNLOAD-PATH")
+            .help("Where the image contents should be saved on the filesystem")
+            .default_value(".")
+            .short("d")
+            .long("download-path"),
+        Arg::with_name("content-path")
+            .value_name("CONTENT-PATH")
+            .help("Where in the container filesystem the content to extract is")
+            .short("c")
+            .default_value("/")
+            .long("content-path"),
+        Arg::with_name("strip-components")
+            .value_name("STRIP-COMPONENTS")
+            .help("Remove the specified number of leading path components from each extracted file")
+            .long("strip-components")
+            .default_value("0"),
+        Arg::with_name("flatten")
+            .value_name("FLATTEN")
+            .help("Place all extracted files directly in the download path. Fails if two files share a name")
+            .takes_value(false)
+            .long("flatten"),
+    ]
+}
+
+// expect_digest_arg returns the argument pinning the digest of the image,
+// for the commands reading a single image
+fn expect_digest_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("expect-digest")
+        .value_name("DIGEST")
+        .help("Fail if the repository digest of the image is not this one, such as sha256:...")
+        .long("expect-digest")
+}
+
+// image_args returns the arguments shared by the commands reading a single
+// image
+fn image_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let mut args = vec![image_arg()];
+    args.extend(runtime_args());
+    args
+}
+
+fn image_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("image")
+        .value_name("IMAGE")
+        .help("Container image to extract content from")
+        .required(true)
+}
+
+// rm_image_arg is only taken by the commands that copy content, the other
+// ones keep the images they pull
+fn rm_image_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("rm-image")
+        .value_name("RM-IMAGE")
+        .help("Remove the image once its content is copied, if it was pulled by this run. Images that were already present locally are kept")
+        .takes_value(false)
+        .long("rm-image")
+}
+
+fn symlinks_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("symlinks")
+        .value_name("SYMLINKS")
+        .help("How symbolic links in the content are written. `rewrite-relative` rewrites absolute links to stay inside the download path, `follow` copies the content they point to instead")
+        .long("symlinks")
+        .possible_values(symlink::POLICIES)
+        .default_value("preserve")
+}
+
+fn special_files_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("special-files")
+        .value_name("SPECIAL-FILES")
+        .help("What to do with device nodes and FIFOs in the content. Creating device nodes requires privileges")
+        .long("special-files")
+        .possible_values(special::POLICIES)
+        .default_value("skip")
+}
+
+// runtime_args returns the arguments shared by every command, connecting to
+// the runtime and pulling images
+fn runtime_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("username")
+            .value_name("USERNAME")
+            .help("Username used for singing into a private registry.")
+            .short("u")
+            .long("username")
+            .default_value(""),
+        Arg::with_name("password")
+            .value_name("PASSWORD")
+            .help("Password used for signing into a private registry. * WARNING *: Writing credentials to your terminal is risky. Be sure you are okay with them showing up in your history")
+            .short("p")
+            .long("password")
+            .default_value(""),
+        Arg::with_name("log-level")
+            .value_name("LOG-LEVEL")
+            .help("What level of logs to output. Accepts: [info, debug, trace, error, warn]")
+            .short("l")
+            .long("log-level")
+            .default_value("debug"),
+        Arg::with_name("quiet")
+            .value_name("QUIET")
+            .help("Do not display progress bars. They are also turned off when stderr is not a terminal")
+            .takes_value(false)
+            .short("q")
+            .long("quiet"),
+        Arg::with_name("force-pull")
+            .value_name("FORCE-PULL")
+            .help("Force a pull even if the image is present locally. Same as --pull always")
+            .takes_value(false)
+            .long("force-pull")
+            .short("f")
+            .conflicts_with("pull"),
+        Arg::with_name("pull")
+            .value_name("PULL")
+            .help("When the image is pulled: `always`, only when `missing` locally, `never`, or when the registry has a `newer` image for the tag")
+            .long("pull")
+            .possible_values(pull::POLICIES)
+            .default_value("missing"),
+        Arg::with_name("socket")
+            .value_name("SOCKET")
+            .help("Specify a custom socket to utilize for the runtime")
+            .long("socket")
+            .short("s")
+            .default_value(runtime::DEFAULT_SOCKET),
+        Arg::with_name("connect-timeout")
+            .value_name("DURATION")
+            .help("How long the runtime has to answer when connecting to its socket, such as `30s`")
+            .long("connect-timeout")
+            .default_value(runtime::DEFAULT_CONNECT_TIMEOUT),
+        Arg::with_name("retries")
+            .value_name("RETRIES")
+            .help("How many times pulling the image or copying its content is retried after a transient error, such as a lost connection or a registry server error")
+            .long("retries")
+            .default_value("2"),
+        Arg::with_name("timeout")
+            .value_name("DURATION")
+            .help("How long the whole run may take before it is aborted, such as `10m`")
+            .long("timeout"),
+        Arg::with_name("policy")
+            .value_name("FILE")
+            .help("Trust policy deciding which images may be pulled. Defaults to the containers-policy.json of the user or /etc/containers/policy.json")
+            .long("policy"),
+        Arg::with_name("insecure-policy")
+            .value_name("INSECURE-POLICY")
+            .help("Do not evaluate any trust policy")
+            .takes_value(false)
+            .long("insecure-policy")
+            .conflicts_with("policy"),
+    ]
+}
+
+// parse_digest checks that digest is a sha256 image digest
+pub(crate) fn parse_digest(digest: &str) -> Result<String> {
+    match digest.strip_prefix("sha256:") {
+        Some(hex) if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(digest.to_lowercase())
+        }
+        _ => Err(anyhow!(
+            "❌ invalid value for --expect-digest: {} is not a sha256:<64 hex characters> digest",
+            digest
+        )),
+    }
+}
+
+// parse_option parses the value of an argument, falling back to the default
+// of its type for the commands that do not take it, such as batches which
+// only read it from the environment and the config files
+fn parse_option<T>(args: &Args, name: &str) -> Result<T>
+where
+    T: std::str::FromStr + Default,
+    anyhow::Error: From<T::Err>,
+{
+    match args.value_of(name) {
+        Some(value) => Ok(value.parse()?),
+        None => Ok(T::default()),
+    }
+}
+
+// parse_limit parses the value of an optional limit argument
+fn parse_limit<T>(args: &Args, name: &str, parse: fn(&str) -> Result<T>) -> Result<Option<T>> {
+    match args.value_of(name) {
+        Some(value) => match parse(value) {
+            Ok(limit) => Ok(Some(limit)),
+            Err(e) => Err(anyhow!("❌ invalid value for --{}: {}", name, e)),
+        },
+        None => Ok(None),
+    }
+}
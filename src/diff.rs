@@ -0,0 +1,285 @@
+use anyhow::Result;
+use serde::Serialize;
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::path::{Component, PathBuf};
+use tar::{Archive, EntryType};
+
+use crate::extract::checksum;
+
+// Largest file whose content is kept to be shown as a unified diff
+const TEXT_LIMIT: u64 = 64 * 1024;
+
+/// File describes an entry of the content of an image
+#[derive(Debug, Clone, Serialize)]
+pub struct File {
+    pub path: PathBuf,
+    // `file`, `dir`, `symlink`, `hardlink` or `other`
+    pub kind: &'static str,
+    pub size: u64,
+    // Permission bits, in octal
+    pub mode: String,
+    // Hex encoded SHA-256 of the content, only set for regular files
+    pub sha256: Option<String>,
+    // Target of links
+    pub link: Option<PathBuf>,
+    // Content of small UTF-8 files, kept for the unified diff
+    #[serde(skip)]
+    text: Option<String>,
+}
+
+/// Change describes a path found in both images that differs between them
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub path: PathBuf,
+    // What differs: `kind`, `size`, `mode`, `content` or `link`
+    pub changes: Vec<&'static str>,
+    pub before: File,
+    pub after: File,
+    // Unified diff of the content, for small text files when requested
+    pub unified: Option<String>,
+}
+
+/// Diff lists the differences between the content of two images, at the
+/// same content path
+#[derive(Debug, Default, Serialize)]
+pub struct Diff {
+    pub old: String,
+    pub new: String,
+    // Paths only found in the new image
+    pub added: Vec<File>,
+    // Paths only found in the old image
+    pub removed: Vec<File>,
+    pub modified: Vec<Change>,
+}
+
+impl Diff {
+    /// Compares the tar archives copied out of two images. Entries are read
+    /// one by one: only the digests of the files, and the content of small
+    /// text files when a unified diff is requested, are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - Image the content is compared from, and its archive
+    /// * `new` - Image the content is compared to, and its archive
+    /// * `unified` - Whether to compute unified diffs of small text files
+    pub fn compare(old: (&str, &[u8]), new: (&str, &[u8]), unified: bool) -> Result<Diff> {
+        let mut before = index(old.1, unified)?;
+        let after = index(new.1, unified)?;
+
+        let mut diff = Diff {
+            old: old.0.to_string(),
+            new: new.0.to_string(),
+            ..Default::default()
+        };
+        for (path, after) in after {
+            let before = match before.remove(&path) {
+                Some(before) => before,
+                None => {
+                    diff.added.push(after);
+                    continue;
+                }
+            };
+
+            let changes = changes(&before, &after);
+            if changes.is_empty() {
+                continue;
+            }
+            let unified = match (&before.text, &after.text) {
+                (Some(old), Some(new)) if changes.contains(&"content") => Some(
+                    TextDiff::from_lines(old, new)
+                        .unified_diff()
+                        .header(
+                            &format!("a/{}", path.display()),
+                            &format!("b/{}", path.display()),
+                        )
+                        .to_string(),
+                ),
+                _ => None,
+            };
+            diff.modified.push(Change {
+                path,
+                changes,
+                before,
+                after,
+                unified,
+            });
+        }
+        diff.removed = before.into_values().collect();
+
+        Ok(diff)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    /// Writes the diff to stdout, as JSON or as human-readable text
+    pub fn write(&self, json: bool) -> Result<()> {
+        let output = if json {
+            let mut output = serde_json::to_string_pretty(self)?;
+            output.push('\n');
+            output
+        } else {
+            self.text()
+        };
+        std::io::stdout().write_all(output.as_bytes())?;
+        Ok(())
+    }
+
+    // text lists the differences one per line, prefixed like a diff, with
+    // the unified diffs below the modified files
+    fn text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "--- {}\n+++ {}", self.old, self.new);
+        for file in &self.added {
+            let _ = writeln!(text, "+ {} ({} bytes)", file.path.display(), file.size);
+        }
+        for file in &self.removed {
+            let _ = writeln!(text, "- {} ({} bytes)", file.path.display(), file.size);
+        }
+        for change in &self.modified {
+            let _ = writeln!(
+                text,
+                "~ {} ({})",
+                change.path.display(),
+                change.changes.join(", ")
+            );
+            if let Some(unified) = &change.unified {
+                text.push_str(unified);
+            }
+        }
+        let _ = writeln!(
+            text,
+            "{} added, {} removed, {} modified",
+            self.added.len(),
+            self.removed.len(),
+            self.modified.len()
+        );
+        text
+    }
+}
+
+// index describes every entry of an archive by its normalized path
+fn index(bytes: &[u8], keep_text: bool) -> Result<BTreeMap<PathBuf, File>> {
+    let mut files = BTreeMap::new();
+    let mut archive = Archive::new(bytes);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path: PathBuf = entry
+            .path()?
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+
+        let header = entry.header();
+        let kind = match header.entry_type() {
+            EntryType::Regular | EntryType::Continuous => "file",
+            EntryType::Directory => "dir",
+            EntryType::Symlink => "symlink",
+            EntryType::Link => "hardlink",
+            _ => "other",
+        };
+        let size = header.size()?;
+        let mode = format!("{:04o}", header.mode()? & 0o7777);
+        let link = entry.link_name()?.map(|link| link.into_owned());
+
+        let (sha256, text) = if kind != "file" {
+            (None, None)
+        } else if keep_text && size <= TEXT_LIMIT {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            let sha256 = checksum::sha256(&content[..])?;
+            (Some(sha256), String::from_utf8(content).ok())
+        } else {
+            (Some(checksum::sha256(&mut entry)?), None)
+        };
+
+        files.insert(
+            path.clone(),
+            File {
+                path,
+                kind,
+                size,
+                mode,
+                sha256,
+                link,
+                text,
+            },
+        );
+    }
+    Ok(files)
+}
+
+// changes lists what differs between two entries at the same path
+fn changes(before: &File, after: &File) -> Vec<&'static str> {
+    let mut changes = Vec::new();
+    if before.kind != after.kind {
+        changes.push("kind");
+    }
+    if before.kind == "file" && before.size != after.size {
+        changes.push("size");
+    }
+    if before.mode != after.mode {
+        changes.push("mode");
+    }
+    if before.sha256 != after.sha256 {
+        changes.push("content");
+    }
+    if before.link != after.link {
+        changes.push("link");
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Diff;
+    use std::path::PathBuf;
+
+    fn archive(files: &[(&str, &str, u32)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content, mode) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(*mode);
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_compare() {
+        let old = archive(&[
+            ("manifests/csv.yaml", "replaces: v0.0.2\n", 0o644),
+            ("manifests/crd.yaml", "kind: CRD\n", 0o644),
+            ("manifests/old.yaml", "kind: Old\n", 0o644),
+        ]);
+        let new = archive(&[
+            ("./manifests/csv.yaml", "replaces: v0.0.3\n", 0o644),
+            ("manifests/crd.yaml", "kind: CRD\n", 0o600),
+            ("manifests/new.yaml", "kind: New\n", 0o644),
+        ]);
+
+        let diff = Diff::compare(("v0.0.3", &old), ("v0.0.4", &new), true).unwrap();
+        assert_eq!(diff.added[0].path, PathBuf::from("manifests/new.yaml"));
+        assert_eq!(diff.removed[0].path, PathBuf::from("manifests/old.yaml"));
+        assert_eq!(diff.modified.len(), 2);
+        assert_eq!(diff.modified[0].changes, vec!["mode"]);
+        assert_eq!(diff.modified[1].changes, vec!["content"]);
+        assert_eq!(
+            diff.modified[1].unified.as_deref(),
+            Some(
+                "--- a/manifests/csv.yaml\n+++ b/manifests/csv.yaml\n@@ -1 +1 @@\n-replaces: v0.0.2\n+replaces: v0.0.3\n"
+            )
+        );
+        assert!(diff.text().ends_with("1 added, 1 removed, 2 modified\n"));
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt;
+
+use crate::extract::limits::{self, LimitExceeded};
+
+/// Error is why a run failed, for library users and scripts to tell the
+/// failures apart. The CLI exits with the code of each kind:
+///
+/// | Code | Kind                 |
+/// |------|----------------------|
+/// | 1    | `Other`              |
+/// | 2    | `Usage`              |
+/// | 3    | `LimitExceeded`      |
+/// | 4    | `NoRuntime`          |
+/// | 5    | `ImageNotFound`      |
+/// | 6    | `AuthFailed`         |
+/// | 7    | `ContentPathMissing` |
+/// | 8    | `UnpackEscape`       |
+/// | 9    | `Rejected`           |
+/// | 10   | `Timeout`            |
+#[derive(Debug)]
+pub enum Error {
+    /// Any other failure
+    Other(String),
+    /// Invalid flags, environment variables, config files or batch files
+    Usage(String),
+    /// The content breaches one of the `--max-*` limits
+    LimitExceeded(String),
+    /// Neither docker nor podman answer on the socket
+    NoRuntime(String),
+    /// The image is not in its registry, or not present locally with `--pull never`
+    ImageNotFound(String),
+    /// The registry refused the credentials
+    AuthFailed(String),
+    /// The content path is not in the image
+    ContentPathMissing(String),
+    /// An entry of the content would be written outside of the destination
+    UnpackEscape(String),
+    /// The trust policy, the expected digest or the signature refused the image
+    Rejected(String),
+    /// The run took longer than `--timeout`
+    Timeout(String),
+}
+
+// Messages of registry errors refusing the credentials, which runtimes may
+// report as server errors
+const AUTH_MESSAGES: &[&str] = &["unauthorized", "authentication required", "denied"];
+
+impl Error {
+    /// Returns the exit code of the CLI for the error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Other(_) => 1,
+            Error::Usage(_) => 2,
+            Error::LimitExceeded(_) => limits::EXIT_CODE,
+            Error::NoRuntime(_) => 4,
+            Error::ImageNotFound(_) => 5,
+            Error::AuthFailed(_) => 6,
+            Error::ContentPathMissing(_) => 7,
+            Error::UnpackEscape(_) => 8,
+            Error::Rejected(_) => 9,
+            Error::Timeout(_) => 10,
+        }
+    }
+
+    /// Returns the message of the error
+    pub fn message(&self) -> &str {
+        match self {
+            Error::Other(message)
+            | Error::Usage(message)
+            | Error::LimitExceeded(message)
+            | Error::NoRuntime(message)
+            | Error::ImageNotFound(message)
+            | Error::AuthFailed(message)
+            | Error::ContentPathMissing(message)
+            | Error::UnpackEscape(message)
+            | Error::Rejected(message)
+            | Error::Timeout(message) => message,
+        }
+    }
+
+    // with_message returns an error of the same kind with another message
+    fn with_message(&self, message: String) -> Error {
+        match self {
+            Error::Other(_) => Error::Other(message),
+            Error::Usage(_) => Error::Usage(message),
+            Error::LimitExceeded(_) => Error::LimitExceeded(message),
+            Error::NoRuntime(_) => Error::NoRuntime(message),
+            Error::ImageNotFound(_) => Error::ImageNotFound(message),
+            Error::AuthFailed(_) => Error::AuthFailed(message),
+            Error::ContentPathMissing(_) => Error::ContentPathMissing(message),
+            Error::UnpackEscape(_) => Error::UnpackEscape(message),
+            Error::Rejected(_) => Error::Rejected(message),
+            Error::Timeout(_) => Error::Timeout(message),
+        }
+    }
+
+    // classify returns the kind of the first typed error in the chain of e,
+    // with the message of e
+    pub(crate) fn classify(e: &anyhow::Error) -> Error {
+        let message = e.to_string().trim_start_matches("❌ ").to_string();
+        for cause in e.chain() {
+            if let Some(error) = cause.downcast_ref::<Error>() {
+                return error.with_message(message);
+            }
+            if cause.is::<LimitExceeded>() {
+                return Error::LimitExceeded(message);
+            }
+        }
+        Error::Other(message)
+    }
+
+    // summarize returns the error of a run of several images: of the kind of
+    // their failures when they all failed the same way, `Other` otherwise
+    pub(crate) fn summarize<'a>(
+        errors: impl IntoIterator<Item = &'a anyhow::Error>,
+        message: String,
+    ) -> Error {
+        let kinds: Vec<Error> = errors.into_iter().map(Error::classify).collect();
+        match kinds.first() {
+            Some(first)
+                if kinds
+                    .iter()
+                    .all(|kind| kind.exit_code() == first.exit_code()) =>
+            {
+                first.with_message(message)
+            }
+            _ => Error::Other(message),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Error::classify(&e)
+    }
+}
+
+/// Returns the error of the runtime failing to pull an image, telling apart
+/// missing images and refused credentials.
+///
+/// # Arguments
+///
+/// * `e` - Error of the runtime
+/// * `message` - Message of the returned error
+pub(crate) fn pull_failed(e: anyhow::Error, message: String) -> anyhow::Error {
+    match status(&e) {
+        Some((404, _)) => Error::ImageNotFound(message).into(),
+        Some((401 | 403, _)) => Error::AuthFailed(message).into(),
+        Some((_, text)) if AUTH_MESSAGES.iter().any(|auth| text.contains(auth)) => {
+            Error::AuthFailed(message).into()
+        }
+        Some((_, text)) if text.contains("manifest unknown") || text.contains("not found") => {
+            Error::ImageNotFound(message).into()
+        }
+        _ => e.context(message),
+    }
+}
+
+/// Returns the error of the runtime failing to copy the content of an
+/// image, telling apart missing content paths. The typed errors of the
+/// extraction, such as an entry escaping the destination, are kept.
+///
+/// # Arguments
+///
+/// * `e` - Error of the runtime or of the extraction
+/// * `message` - Message of the returned error
+pub(crate) fn copy_failed(e: anyhow::Error, message: String) -> anyhow::Error {
+    match status(&e) {
+        Some((404, text)) if text.contains("no such image") => Error::ImageNotFound(message).into(),
+        Some((404, _)) => Error::ContentPathMissing(message).into(),
+        _ => e.context(message),
+    }
+}
+
+// status returns the status and the lowercase message of the error response
+// of the runtime in the chain of e, if any
+fn status(e: &anyhow::Error) -> Option<(u16, String)> {
+    e.chain().find_map(|cause| {
+        let (code, message) = if let Some(e) = cause.downcast_ref::<docker_api::Error>() {
+            match e {
+                docker_api::Error::Fault { code, message } => (code.as_u16(), message),
+                docker_api::Error::Error(docker_api::conn::Error::Fault { code, message }) => {
+                    (code.as_u16(), message)
+                }
+                _ => return None,
+            }
+        } else if let Some(e) = cause.downcast_ref::<podman_api::Error>() {
+            match e {
+                podman_api::Error::Fault { code, message } => (code.as_u16(), message),
+                podman_api::Error::Error(podman_api::conn::Error::Fault { code, message }) => {
+                    (code.as_u16(), message)
+                }
+                _ => return None,
+            }
+        } else {
+            return None;
+        };
+        Some((code, message.to_lowercase()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy_failed, Error};
+    use crate::extract::limits::LimitExceeded;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_classify() {
+        let escape = anyhow::Error::new(Error::UnpackEscape("../etc".to_string()));
+        let e = Error::from(copy_failed(escape, "❌ error copying".to_string()));
+        assert!(matches!(e, Error::UnpackEscape(_)));
+        assert_eq!(e.to_string(), "error copying");
+        assert_eq!(e.exit_code(), 8);
+
+        let limit = anyhow::Error::new(LimitExceeded::Entries(10));
+        assert_eq!(Error::from(limit).exit_code(), 3);
+        assert!(matches!(Error::from(anyhow!("❌ failed")), Error::Other(_)));
+
+        let rejected = anyhow::Error::new(Error::Rejected("rejected".to_string()));
+        let other = anyhow!("failed");
+        let e = Error::summarize([&rejected, &rejected], "2 failed".to_string());
+        assert!(matches!(e, Error::Rejected(_)));
+        let e = Error::summarize([&rejected, &other], "2 failed".to_string());
+        assert!(matches!(e, Error::Other(_)));
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Checksum algorithms accepted by `--checksums`
+pub const ALGORITHMS: &[&str] = &["sha256"];
+
+/// Manifest styles accepted by `--checksums-style`
+pub const STYLES: &[&str] = &["gnu", "bsd"];
+
+/// Algorithm is the hash function used for the checksum manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+}
+
+impl Algorithm {
+    /// Name of the manifest file written in the download path
+    pub fn manifest(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA256SUMS",
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sha256" => Ok(Algorithm::Sha256),
+            _ => Err(anyhow!("unsupported checksum algorithm {}", s)),
+        }
+    }
+}
+
+/// Style decides how each line of the checksum manifest is written.
+///
+/// * `Gnu` - `<digest>  <path>`, as read by `sha256sum -c`.
+/// * `Bsd` - `SHA256 (<path>) = <digest>`, as read by `shasum -c` and BSD `sha256 -c`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    #[default]
+    Gnu,
+    Bsd,
+}
+
+impl FromStr for Style {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "gnu" => Ok(Style::Gnu),
+            "bsd" => Ok(Style::Bsd),
+            _ => Err(anyhow!("unsupported checksum style {}", s)),
+        }
+    }
+}
+
+/// Checksums configures the manifest written next to the extracted content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksums {
+    pub algorithm: Algorithm,
+    pub style: Style,
+}
+
+/// Returns the hex encoded SHA-256 digest of everything read from reader
+pub fn sha256<R: Read>(mut reader: R) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+// write writes the checksum manifest of the regular files among files, which
+// are relative to root, and returns its path. Links and special files are
+// left out.
+pub(super) fn write(root: &Path, files: &[PathBuf], checksums: &Checksums) -> Result<PathBuf> {
+    let manifest = root.join(checksums.algorithm.manifest());
+    let mut files: Vec<&PathBuf> = files
+        .iter()
+        .filter(|file| file.as_os_str() != checksums.algorithm.manifest())
+        .collect();
+    files.sort();
+    files.dedup();
+
+    let mut content = String::new();
+    for file in files {
+        let path = root.join(file);
+        if !fs::symlink_metadata(&path)?.is_file() {
+            continue;
+        }
+        let digest = match checksums.algorithm {
+            Algorithm::Sha256 => sha256(fs::File::open(&path)?)?,
+        };
+        content.push_str(&line(checksums, file, &digest));
+        content.push('\n');
+    }
+
+    // The manifest of a previous run is replaced, and never written through
+    // a link that would point outside of the download path
+    if fs::symlink_metadata(&manifest).is_ok() {
+        fs::remove_file(&manifest)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NOFOLLOW);
+    }
+    options.open(&manifest)?.write_all(content.as_bytes())?;
+    Ok(manifest)
+}
+
+// line formats a single manifest entry in the configured style
+fn line(checksums: &Checksums, path: &Path, digest: &str) -> String {
+    match (checksums.style, checksums.algorithm) {
+        (Style::Gnu, _) => format!("{}  {}", digest, path.display()),
+        (Style::Bsd, Algorithm::Sha256) => format!("SHA256 ({}) = {}", path.display(), digest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{line, sha256, write, Algorithm, Checksums, Style};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_line() {
+        let digest = sha256(&b"hello"[..]).unwrap();
+        assert_eq!(
+            digest,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+
+        let mut checksums = Checksums {
+            algorithm: Algorithm::Sha256,
+            style: Style::Gnu,
+        };
+        assert_eq!(
+            line(&checksums, Path::new("crds/a.yaml"), &digest),
+            format!("{}  crds/a.yaml", digest)
+        );
+        checksums.style = Style::Bsd;
+        assert_eq!(
+            line(&checksums, Path::new("crds/a.yaml"), &digest),
+            format!("SHA256 (crds/a.yaml) = {}", digest)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_over_link() {
+        let dir = std::env::temp_dir().join(format!("dcp-checksums-{}", std::process::id()));
+        let root = dir.join("out");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(dir.join("host"), "host").unwrap();
+        fs::write(root.join("a.yaml"), "a").unwrap();
+        std::os::unix::fs::symlink(dir.join("host"), root.join("SHA256SUMS")).unwrap();
+
+        let checksums = Checksums {
+            algorithm: Algorithm::Sha256,
+            style: Style::Gnu,
+        };
+        let manifest = write(&root, &[PathBuf::from("a.yaml")], &checksums).unwrap();
+        let host = fs::read_to_string(dir.join("host")).unwrap();
+        let is_file = fs::symlink_metadata(&manifest).unwrap().is_file();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(host, "host");
+        assert!(is_file);
+    }
+}
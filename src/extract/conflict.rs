@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Conflict policies accepted by `--on-conflict`
+pub const POLICIES: &[&str] = &["overwrite", "skip", "error", "backup"];
+
+/// Conflict decides what happens when a file about to be extracted already
+/// exists in the download path.
+///
+/// * `Overwrite` - Replace the existing file.
+/// * `Skip` - Keep the existing file and do not extract the new one.
+/// * `Error` - Fail before anything is written.
+/// * `Backup` - Rename the existing file with a `~` suffix and extract the new one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    #[default]
+    Overwrite,
+    Skip,
+    Error,
+    Backup,
+}
+
+impl Conflict {
+    // Describes what happened to the existing files, for the final summary
+    pub fn action(&self) -> &'static str {
+        match self {
+            Conflict::Overwrite => "overwritten",
+            Conflict::Skip => "kept",
+            Conflict::Error => "refused",
+            Conflict::Backup => "backed up",
+        }
+    }
+}
+
+impl FromStr for Conflict {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "overwrite" => Ok(Conflict::Overwrite),
+            "skip" => Ok(Conflict::Skip),
+            "error" => Ok(Conflict::Error),
+            "backup" => Ok(Conflict::Backup),
+            _ => Err(anyhow!("unsupported conflict policy {}", s)),
+        }
+    }
+}
+
+// backup renames the file at path out of the way and returns its new name.
+// The first backup is named `path~`, later ones `path.~N~` like GNU cp does.
+pub(super) fn backup(path: &Path) -> Result<PathBuf> {
+    let mut name = OsString::from(path.as_os_str());
+    name.push("~");
+    let mut backup = PathBuf::from(name);
+
+    let mut n = 1;
+    while fs::symlink_metadata(&backup).is_ok() {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(format!(".~{}~", n));
+        backup = PathBuf::from(name);
+        n += 1;
+    }
+
+    fs::rename(path, &backup)?;
+    Ok(backup)
+}
+
+// ensure_empty fails if the download path exists and already has content
+pub(super) fn ensure_empty(root: &Path) -> Result<()> {
+    if let Ok(mut entries) = fs::read_dir(root) {
+        if entries.next().is_some() {
+            return Err(anyhow!("download path {} is not empty", root.display()));
+        }
+    }
+    Ok(())
+}
+
+// clean removes everything inside the download path, keeping the directory
+pub(super) fn clean(root: &Path) -> Result<()> {
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(()),
+    };
+    ensure_cleanable(root)?;
+
+    for entry in entries {
+        let path = entry?.path();
+        if fs::symlink_metadata(&path)?.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    debug!("🔧 Cleaned download path {}", root.display());
+    Ok(())
+}
+
+// ensure_cleanable refuses to clean the filesystem root and the directories
+// holding the working directory or the home directory, such as the default
+// download path `.`
+pub(super) fn ensure_cleanable(root: &Path) -> Result<()> {
+    // A download path that does not exist yet holds nothing
+    let root = match fs::canonicalize(root) {
+        Ok(root) => root,
+        Err(_) => return Ok(()),
+    };
+    if root.parent().is_none() {
+        return Err(anyhow!("refusing to clean the filesystem root"));
+    }
+
+    let protected = [
+        ("working directory", std::env::current_dir().ok()),
+        (
+            "home directory",
+            std::env::var_os("HOME").map(PathBuf::from),
+        ),
+    ];
+    for (name, dir) in protected {
+        match dir.and_then(|dir| fs::canonicalize(dir).ok()) {
+            Some(dir) if dir.starts_with(&root) => {
+                return Err(anyhow!(
+                    "refusing to clean {} as it holds the {}, pass another --download-path",
+                    root.display(),
+                    name
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clean, ensure_cleanable};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_clean() {
+        let dir = std::env::temp_dir().join(format!("dcp-clean-{}", std::process::id()));
+        fs::create_dir_all(dir.join("manifests")).unwrap();
+        fs::write(dir.join("manifests").join("a.yaml"), "a").unwrap();
+        let cleaned = clean(&dir);
+        let empty = fs::read_dir(&dir).unwrap().next().is_none();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(cleaned.is_ok(), "{:?}", cleaned);
+        assert!(empty);
+        assert!(ensure_cleanable(Path::new("/")).is_err());
+        assert!(ensure_cleanable(Path::new(".")).is_err());
+        assert!(ensure_cleanable(Path::new("..")).is_err());
+    }
+}
@@ -0,0 +1,455 @@
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, Builder, EntryType};
+
+use super::limits::Limits;
+
+// Prefix of the whiteout files marking paths deleted by a layer
+const WHITEOUT_PREFIX: &str = ".wh.";
+// Whiteout hiding the content lower layers have in its directory
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+// Magic numbers of the compressed layer blobs
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Selection is the set of layers extracted with `--layer` or `--layers`
+/// instead of the merged filesystem of the image. Layers are numbered from
+/// the base layer, starting at 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selection {
+    // The layer at this position
+    Index(usize),
+    // The layer with this diff id, or a unique prefix of it
+    Digest(String),
+    // The layers between these positions, both included. Open ends run to
+    // the first or last layer
+    Range(Option<usize>, Option<usize>),
+}
+
+impl Selection {
+    /// Parses the value of `--layer`, either a position or a `sha256:...` digest
+    pub fn parse_layer(layer: &str) -> Result<Selection> {
+        if layer.starts_with("sha256:") {
+            return Ok(Selection::Digest(layer.to_lowercase()));
+        }
+        match layer.parse() {
+            Ok(index) => Ok(Selection::Index(index)),
+            Err(_) => Err(anyhow!(
+                "{} is neither a layer position nor a sha256:... digest",
+                layer
+            )),
+        }
+    }
+
+    /// Parses the value of `--layers`, such as `2..4`, `3..` or `..1`
+    pub fn parse_range(range: &str) -> Result<Selection> {
+        let (start, end) = match range.split_once("..") {
+            Some(bounds) => bounds,
+            None => return Err(anyhow!("{} is not a range such as 2..4", range)),
+        };
+        let parse = |bound: &str| -> Result<Option<usize>> {
+            match bound {
+                "" => Ok(None),
+                bound => Ok(Some(bound.parse()?)),
+            }
+        };
+
+        let (start, end) = (parse(start)?, parse(end)?);
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Err(anyhow!("range {} starts after it ends", range));
+            }
+        }
+        Ok(Selection::Range(start, end))
+    }
+
+    // select returns the positions of the selected layers among the diff ids
+    // of the image
+    fn select(&self, diff_ids: &[String]) -> Result<Range<usize>> {
+        let count = diff_ids.len();
+        let out_of_range = |index: usize| {
+            anyhow!(
+                "layer {} does not exist, the image has {} layers",
+                index,
+                count
+            )
+        };
+
+        match self {
+            Selection::Index(index) if *index < count => Ok(*index..*index + 1),
+            Selection::Index(index) => Err(out_of_range(*index)),
+            Selection::Digest(digest) => {
+                if let Some(index) = diff_ids.iter().position(|diff_id| diff_id == digest) {
+                    return Ok(index..index + 1);
+                }
+                let mut found = diff_ids
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, diff_id)| diff_id.starts_with(digest.as_str()));
+                match (found.next(), found.next()) {
+                    (Some((index, _)), None) => Ok(index..index + 1),
+                    (Some(_), Some(_)) => Err(anyhow!("{} matches several layers", digest)),
+                    (None, _) => Err(anyhow!("the image has no layer {}", digest)),
+                }
+            }
+            Selection::Range(start, end) => {
+                let start = start.unwrap_or(0);
+                let end = end.unwrap_or_else(|| count.saturating_sub(1));
+                if start >= count || start > end {
+                    return Err(out_of_range(start));
+                }
+                if end >= count {
+                    return Err(out_of_range(end));
+                }
+                Ok(start..end + 1)
+            }
+        }
+    }
+}
+
+/// Layer is a single layer read out of an image
+#[derive(Debug)]
+pub struct Layer {
+    // Position of the layer, the base layer being 0
+    pub index: usize,
+    // Diff id of the layer, the digest of its uncompressed archive
+    pub digest: String,
+    // Uncompressed tar archive of the layer
+    pub bytes: Vec<u8>,
+}
+
+/// Content is the archive built out of the selected layers, as if it had
+/// been copied out of a container
+#[derive(Debug, Default)]
+pub struct Content {
+    pub bytes: Vec<u8>,
+    // Paths of the image deleted by the layers. Opaque directories, whose
+    // content in lower layers is hidden, are listed as the directory itself
+    pub whiteouts: Vec<PathBuf>,
+}
+
+// Manifest is an entry of the manifest.json of a docker-archive
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Manifest {
+    config: String,
+    layers: Vec<String>,
+}
+
+// Config is the part of the image configuration listing its layers
+#[derive(Deserialize)]
+struct Config {
+    rootfs: RootFs,
+}
+
+#[derive(Deserialize)]
+struct RootFs {
+    diff_ids: Vec<String>,
+}
+
+/// Reads the selected layers out of an image saved in the docker-archive
+/// format, as exported by both docker and podman. Compressed layer blobs are
+/// decompressed, the size of the decompressed layers counting against the
+/// total size limit.
+///
+/// # Arguments
+///
+/// * `image` - The image archive exported by the runtime
+/// * `selection` - Which layers to read
+/// * `limits` - Limits of the content
+pub fn read(image: &[u8], selection: &Selection, limits: &Limits) -> Result<Vec<Layer>> {
+    let files = index(image)?;
+    let file = |name: &str| match files.get(&normalize(Path::new(name))) {
+        Some(range) => Ok(&image[range.clone()]),
+        None => Err(anyhow!("the image archive has no {}", name)),
+    };
+
+    let manifests: Vec<Manifest> = serde_json::from_slice(file("manifest.json")?)?;
+    let manifest = match manifests.into_iter().next() {
+        Some(manifest) => manifest,
+        None => return Err(anyhow!("the image archive has an empty manifest")),
+    };
+    let config: Config = serde_json::from_slice(file(&manifest.config)?)?;
+    let diff_ids = config.rootfs.diff_ids;
+    if diff_ids.len() != manifest.layers.len() {
+        return Err(anyhow!(
+            "the image archive has {} layers but its configuration lists {}",
+            manifest.layers.len(),
+            diff_ids.len()
+        ));
+    }
+
+    let mut layers = Vec::new();
+    let mut total = 0;
+    for index in selection.select(&diff_ids)? {
+        layers.push(Layer {
+            index,
+            digest: diff_ids[index].clone(),
+            bytes: decompress(file(&manifest.layers[index])?, limits, &mut total)?,
+        });
+    }
+    Ok(layers)
+}
+
+/// Builds a single archive out of the layers, in order, holding what they
+/// add under the content path. Paths are relative to the directory holding
+/// the content path, as in the archives copied out of containers. Whiteouts
+/// are listed instead of being applied.
+///
+/// # Arguments
+///
+/// * `layers` - Layers to combine, base layer first
+/// * `content_path` - Where in the image filesystem the content to extract is
+pub fn combine(layers: &[Layer], content_path: &Path) -> Result<Content> {
+    let content = normalize(content_path);
+    // Components of the image paths that are not part of the archive paths
+    let parent = content.len().saturating_sub(1);
+    let rewrite = |path: &Path| -> Option<PathBuf> {
+        let components = normalize(path);
+        if !components.starts_with(&content) || components.len() <= parent {
+            return None;
+        }
+        Some(components[parent..].iter().collect())
+    };
+
+    let mut whiteouts = Vec::new();
+    let mut builder = Builder::new(Vec::new());
+    for layer in layers {
+        let mut archive = Archive::new(&layer.bytes[..]);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path: PathBuf = normalize(&entry.path()?).iter().collect();
+            let relative = match rewrite(&path) {
+                Some(relative) => relative,
+                None => continue,
+            };
+
+            if let Some(deleted) = whiteout(&path) {
+                whiteouts.push(Path::new("/").join(deleted));
+                continue;
+            }
+
+            let mut header = entry.header().clone();
+            match header.entry_type() {
+                EntryType::Symlink => {
+                    let target = entry.link_name()?.unwrap_or_default().into_owned();
+                    builder.append_link(&mut header, &relative, target)?;
+                }
+                EntryType::Link => {
+                    let target = entry.link_name()?.unwrap_or_default().into_owned();
+                    match rewrite(&target) {
+                        Some(target) => builder.append_link(&mut header, &relative, target)?,
+                        None => warn!(
+                            "⚠️ Skipping hard link {} to {}, outside of the content path",
+                            path.display(),
+                            target.display()
+                        ),
+                    }
+                }
+                _ => builder.append_data(&mut header, &relative, &mut entry)?,
+            }
+        }
+    }
+
+    Ok(Content {
+        bytes: builder.into_inner()?,
+        whiteouts,
+    })
+}
+
+// index maps the normalized paths of the files of the image archive to their
+// position in it. Symlinks, used by docker to share identical layers, are
+// resolved to the file they point to.
+fn index(image: &[u8]) -> Result<HashMap<Vec<OsString>, Range<usize>>> {
+    let mut files = HashMap::new();
+    let mut links = Vec::new();
+
+    let mut archive = Archive::new(image);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = normalize(&entry.path()?);
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous => {
+                let start = entry.raw_file_position() as usize;
+                files.insert(path, start..start + entry.size() as usize);
+            }
+            EntryType::Symlink => {
+                if let Some(target) = entry.link_name()? {
+                    let dir: PathBuf = path[..path.len().saturating_sub(1)].iter().collect();
+                    links.push((path, normalize(&dir.join(target))));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (path, target) in links {
+        if let Some(range) = files.get(&target).cloned() {
+            files.insert(path, range);
+        }
+    }
+    Ok(files)
+}
+
+// whiteout returns the path deleted by a whiteout entry, or None if the
+// entry is not a whiteout
+fn whiteout(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    if name == OPAQUE_WHITEOUT {
+        return Some(dir.to_path_buf());
+    }
+    name.strip_prefix(WHITEOUT_PREFIX)
+        .map(|deleted| dir.join(deleted))
+}
+
+// decompress returns the uncompressed archive of a layer blob. The bytes
+// are added to total as they are decompressed, failing as soon as it
+// exceeds the total size limit so that a compression bomb is never held in
+// memory.
+fn decompress(blob: &[u8], limits: &Limits, total: &mut usize) -> Result<Vec<u8>> {
+    let mut reader: Box<dyn Read + '_> = if blob.starts_with(GZIP_MAGIC) {
+        Box::new(GzDecoder::new(blob))
+    } else if blob.starts_with(ZSTD_MAGIC) {
+        Box::new(zstd::Decoder::new(blob)?)
+    } else {
+        Box::new(blob)
+    };
+
+    let mut bytes = Vec::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(bytes);
+        }
+        *total += read;
+        limits.check_stream(*total)?;
+        bytes.extend_from_slice(&buffer[..read]);
+    }
+}
+
+// normalize returns the components of a path of the image, resolving `.`
+// and `..` without leaving the root
+fn normalize(path: &Path) -> Vec<OsString> {
+    let mut components = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_os_string()),
+            Component::ParentDir => {
+                components.pop();
+            }
+            _ => {}
+        }
+    }
+    components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{combine, decompress, Layer, Selection};
+    use crate::extract::limits::Limits;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    fn layer(index: usize, files: &[&str]) -> Layer {
+        let mut builder = tar::Builder::new(Vec::new());
+        for path in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(4);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, path, &b"data"[..])
+                .unwrap();
+        }
+        Layer {
+            index,
+            digest: format!("sha256:{}", index),
+            bytes: builder.into_inner().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let diff_ids: Vec<String> = ["sha256:aa11", "sha256:bb22", "sha256:bb33", "sha256:aa110"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+
+        let select = |value: &str, range: bool| {
+            let selection = if range {
+                Selection::parse_range(value)
+            } else {
+                Selection::parse_layer(value)
+            };
+            selection.and_then(|selection| selection.select(&diff_ids))
+        };
+        assert_eq!(select("1", false).unwrap(), 1..2);
+        assert_eq!(select("sha256:bb3", false).unwrap(), 2..3);
+        assert!(select("sha256:bb", false).is_err());
+        assert!(select("4", false).is_err());
+        assert_eq!(select("sha256:aa11", false).unwrap(), 0..1);
+        assert_eq!(select("1..", true).unwrap(), 1..4);
+        assert_eq!(select("..1", true).unwrap(), 0..2);
+        assert!(select("2..1", true).is_err());
+        // 5.. would select no layer of the 4 of the image
+        assert!(select("5..", true).is_err());
+        assert!(select("4..", true).is_err());
+        assert!(select("4..9", true).is_err());
+        assert_eq!(select("3..", true).unwrap(), 3..4);
+        assert!(select("latest", false).is_err());
+    }
+
+    #[test]
+    fn test_decompress() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&vec![0; 1 << 20]).unwrap();
+        let blob = encoder.finish().unwrap();
+
+        let mut total = 0;
+        let bytes = decompress(&blob, &Limits::default(), &mut total).unwrap();
+        assert_eq!(bytes.len(), 1 << 20);
+        assert_eq!(total, 1 << 20);
+
+        // the limit is reached long before the whole layer is decompressed
+        let limits = Limits {
+            max_total_size: Some(1 << 16),
+            ..Default::default()
+        };
+        let mut total = 0;
+        assert!(decompress(&blob, &limits, &mut total).is_err());
+        assert!(total < 1 << 20);
+    }
+
+    #[test]
+    fn test_combine() {
+        let layers = vec![
+            layer(0, &["manifests/a.yaml", "metadata/annotations.yaml"]),
+            layer(1, &["./manifests/b.yaml", "manifests/.wh.a.yaml"]),
+        ];
+
+        let content = combine(&layers, Path::new("/manifests")).unwrap();
+        assert_eq!(content.whiteouts, vec![PathBuf::from("/manifests/a.yaml")]);
+
+        let mut archive = tar::Archive::new(&content.bytes[..]);
+        let paths: Vec<PathBuf> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().into_owned())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("manifests/a.yaml"),
+                PathBuf::from("manifests/b.yaml")
+            ]
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tar::Archive;
+
+/// Exit code of the CLI when the copied content breaches one of the limits
+pub const EXIT_CODE: i32 = 3;
+
+/// Limits guard against hostile images, such as tar bombs. Unset limits are
+/// not enforced.
+#[derive(Debug, Default, Clone)]
+pub struct Limits {
+    // Maximum size in bytes of the archive copied out of the container
+    pub max_total_size: Option<u64>,
+    // Maximum size in bytes of a single file
+    pub max_file_size: Option<u64>,
+    // Maximum number of entries in the archive
+    pub max_entries: Option<u64>,
+    // Maximum number of components in the path of an entry
+    pub max_path_depth: Option<usize>,
+}
+
+/// LimitExceeded is the error returned when the copied content breaches
+/// one of the configured limits.
+#[derive(Debug)]
+pub enum LimitExceeded {
+    TotalSize(u64),
+    FileSize(PathBuf, u64),
+    Entries(u64),
+    PathDepth(PathBuf, usize),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::TotalSize(max) => {
+                write!(f, "content exceeds the maximum total size of {} bytes", max)
+            }
+            LimitExceeded::FileSize(path, max) => write!(
+                f,
+                "{} exceeds the maximum file size of {} bytes",
+                path.display(),
+                max
+            ),
+            LimitExceeded::Entries(max) => {
+                write!(f, "content exceeds the maximum of {} entries", max)
+            }
+            LimitExceeded::PathDepth(path, max) => write!(
+                f,
+                "{} exceeds the maximum path depth of {}",
+                path.display(),
+                max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Usage counts the bytes and entries written so far, for the content that
+/// is written more than once, such as directories copied by following
+/// symlinks.
+#[derive(Debug, Default)]
+pub struct Usage {
+    pub bytes: u64,
+    pub entries: u64,
+}
+
+impl Limits {
+    /// Checks the number of bytes received so far while the archive is
+    /// streamed from the container runtime.
+    pub fn check_stream(&self, received: usize) -> Result<(), LimitExceeded> {
+        match self.max_total_size {
+            Some(max) if received as u64 > max => Err(LimitExceeded::TotalSize(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Charges an entry written locally to the usage, failing when the
+    /// content written so far breaches one of the limits.
+    pub fn charge(&self, usage: &mut Usage, path: &Path, size: u64) -> Result<(), LimitExceeded> {
+        usage.bytes = usage.bytes.saturating_add(size);
+        usage.entries += 1;
+
+        match self.max_total_size {
+            Some(max) if usage.bytes > max => return Err(LimitExceeded::TotalSize(max)),
+            _ => {}
+        }
+        match self.max_entries {
+            Some(max) if usage.entries > max => return Err(LimitExceeded::Entries(max)),
+            _ => {}
+        }
+        match self.max_file_size {
+            Some(max) if size > max => return Err(LimitExceeded::FileSize(path.into(), max)),
+            _ => {}
+        }
+        match self.max_path_depth {
+            Some(max) if depth(path) > max => Err(LimitExceeded::PathDepth(path.into(), max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks every entry of the archive against the limits before anything
+    /// is written locally, so that a breach never leaves partial output.
+    pub fn check(&self, bytes: &[u8]) -> Result<()> {
+        self.check_stream(bytes.len())?;
+        if self.max_file_size.is_none()
+            && self.max_entries.is_none()
+            && self.max_path_depth.is_none()
+        {
+            return Ok(());
+        }
+
+        let mut entries = 0;
+        let mut archive = Archive::new(bytes);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = entry.path()?;
+
+            entries += 1;
+            if let Some(max) = self.max_entries {
+                if entries > max {
+                    return Err(LimitExceeded::Entries(max).into());
+                }
+            }
+            if let Some(max) = self.max_file_size {
+                if entry.size() > max {
+                    return Err(LimitExceeded::FileSize(path.into_owned(), max).into());
+                }
+            }
+            if let Some(max) = self.max_path_depth {
+                if depth(&path) > max {
+                    return Err(LimitExceeded::PathDepth(path.into_owned(), max).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// depth counts the named components of an archive path
+fn depth(path: &Path) -> usize {
+    path.components()
+        .filter(|component| matches!(component, std::path::Component::Normal(_)))
+        .count()
+}
+
+/// Parses a size in bytes, optionally suffixed with K, M, G or T (powers of 1024)
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let (number, multiplier) = match size.char_indices().last() {
+        Some((i, unit)) if unit.is_ascii_alphabetic() => {
+            let multiplier: u64 = match unit.to_ascii_uppercase() {
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => return Err(anyhow!("unknown size unit {} in {}", unit, size)),
+            };
+            (&size[..i], multiplier)
+        }
+        _ => (size, 1),
+    };
+
+    match number.parse::<u64>() {
+        Ok(number) => match number.checked_mul(multiplier) {
+            Some(bytes) => Ok(bytes),
+            None => Err(anyhow!("size {} is too large", size)),
+        },
+        Err(_) => Err(anyhow!("invalid size {}", size)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_size, Limits, Usage};
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("4K").unwrap(), 4096);
+        assert_eq!(parse_size("1g").unwrap(), 1 << 30);
+        assert!(parse_size("1X").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header.clone(), "a/b/c/file", &b"data"[..])
+            .unwrap();
+        builder
+            .append_data(&mut header, "file", &b"data"[..])
+            .unwrap();
+        let bytes = builder.into_inner().unwrap();
+
+        assert!(Limits::default().check(&bytes).is_ok());
+        let limits = Limits {
+            max_file_size: Some(4),
+            max_entries: Some(2),
+            max_path_depth: Some(4),
+            ..Default::default()
+        };
+        assert!(limits.check(&bytes).is_ok());
+
+        for limits in [
+            Limits {
+                max_total_size: Some(1024),
+                ..Default::default()
+            },
+            Limits {
+                max_file_size: Some(3),
+                ..Default::default()
+            },
+            Limits {
+                max_entries: Some(1),
+                ..Default::default()
+            },
+            Limits {
+                max_path_depth: Some(3),
+                ..Default::default()
+            },
+        ] {
+            assert!(limits.check(&bytes).is_err(), "{:?}", limits);
+        }
+    }
+
+    #[test]
+    fn test_charge() {
+        let limits = Limits {
+            max_total_size: Some(10),
+            max_entries: Some(3),
+            ..Default::default()
+        };
+        let mut usage = Usage::default();
+        assert!(limits.charge(&mut usage, Path::new("a"), 4).is_ok());
+        assert!(limits.charge(&mut usage, Path::new("b"), 4).is_ok());
+        assert!(limits.charge(&mut usage, Path::new("c"), 4).is_err());
+
+        let mut usage = Usage::default();
+        for _ in 0..3 {
+            assert!(limits.charge(&mut usage, Path::new("d"), 0).is_ok());
+        }
+        assert!(limits.charge(&mut usage, Path::new("d"), 0).is_err());
+    }
+}
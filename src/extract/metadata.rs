@@ -0,0 +1,173 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use std::str::FromStr;
+#[cfg(unix)]
+use std::sync::OnceLock;
+
+/// Mtime decides which modification time extracted files get.
+///
+/// * `Archive` - The modification time recorded in the image.
+/// * `Now` - The time the file was extracted.
+/// * `Epoch` - A fixed number of seconds since the unix epoch, for reproducible outputs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mtime {
+    #[default]
+    Archive,
+    Now,
+    Epoch(i64),
+}
+
+impl FromStr for Mtime {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "archive" => Ok(Mtime::Archive),
+            "now" => Ok(Mtime::Now),
+            epoch => match epoch.parse() {
+                Ok(seconds) => Ok(Mtime::Epoch(seconds)),
+                Err(_) => Err(anyhow!(
+                    "mtime must be one of `archive`, `now` or seconds since the unix epoch, got {}",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Parses an owner given as `uid:gid` into its numeric ids
+pub fn parse_owner(owner: &str) -> Result<(u32, u32)> {
+    let (uid, gid) = match owner.split_once(':') {
+        Some(ids) => ids,
+        None => return Err(anyhow!("owner must be given as uid:gid, got {}", owner)),
+    };
+    match (uid.parse(), gid.parse()) {
+        (Ok(uid), Ok(gid)) => Ok((uid, gid)),
+        _ => Err(anyhow!("uid and gid must be numeric, got {}", owner)),
+    }
+}
+
+// Metadata is the post-processing applied to every extracted path once its
+// content has been written, for the settings `tar::Archive` cannot express.
+#[derive(Debug, Default)]
+pub(super) struct Metadata {
+    pub umask: Option<u32>,
+    pub strip_setuid: bool,
+    pub mtime: Option<i64>,
+    pub chown: Option<(u32, u32)>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self.umask.is_none() && !self.strip_setuid && self.mtime.is_none() && self.chown.is_none()
+    }
+
+    // apply adjusts the owner, mode and modification time of path. Symlinks
+    // are never followed.
+    pub fn apply(&self, path: &Path) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        // Entries skipped by the symlink policy were never written
+        let metadata = match std::fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(()),
+        };
+        self.apply_unix(path, &metadata)?;
+
+        if let Some(seconds) = self.mtime {
+            let time = filetime::FileTime::from_unix_time(seconds, 0);
+            filetime::set_symlink_file_times(path, time, time)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn apply_unix(&self, path: &Path, metadata: &std::fs::Metadata) -> Result<()> {
+        use std::os::unix::fs::{lchown, PermissionsExt};
+
+        if let Some((uid, gid)) = self.chown {
+            lchown(path, Some(uid), Some(gid))?;
+        }
+
+        // Permissions of symlinks cannot be changed on most platforms
+        if metadata.file_type().is_symlink() {
+            return Ok(());
+        }
+
+        let mut mode = metadata.permissions().mode() & 0o7777;
+        if let Some(umask) = self.umask {
+            mode &= !umask;
+        }
+        if self.strip_setuid {
+            mode &= !0o6000;
+        }
+        if mode != metadata.permissions().mode() & 0o7777 {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply_unix(&self, _path: &Path, _metadata: &std::fs::Metadata) -> Result<()> {
+        Ok(())
+    }
+}
+
+// umask returns the file mode creation mask of the current process. It is
+// read from /proc when available and only once, as setting it to read it
+// races with the files other threads create.
+#[cfg(unix)]
+pub(super) fn umask() -> u32 {
+    static UMASK: OnceLock<u32> = OnceLock::new();
+    *UMASK.get_or_init(|| match std::fs::read_to_string("/proc/self/status") {
+        Ok(status) => parse_umask(&status).unwrap_or(0o022),
+        // There is no other way to read the umask than setting it, so it is
+        // set and restored straight away
+        Err(_) => unsafe {
+            let mask = libc::umask(0o022);
+            libc::umask(mask);
+            mask as u32
+        },
+    })
+}
+
+// parse_umask returns the octal Umask field of /proc/self/status
+#[cfg(unix)]
+fn parse_umask(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Umask:"))
+        .and_then(|mask| u32::from_str_radix(mask.trim(), 8).ok())
+}
+
+#[cfg(not(unix))]
+pub(super) fn umask() -> u32 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_owner, parse_umask, Mtime};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("archive".parse::<Mtime>().unwrap(), Mtime::Archive);
+        assert_eq!("now".parse::<Mtime>().unwrap(), Mtime::Now);
+        assert_eq!("0".parse::<Mtime>().unwrap(), Mtime::Epoch(0));
+        assert!("yesterday".parse::<Mtime>().is_err());
+
+        assert_eq!(parse_owner("1000:100").unwrap(), (1000, 100));
+        assert!(parse_owner("1000").is_err());
+        assert!(parse_owner("root:root").is_err());
+
+        assert_eq!(
+            parse_umask("Name:\tdcp\nUmask:\t0027\nState:\tR\n"),
+            Some(0o027)
+        );
+        assert_eq!(parse_umask("Name:\tdcp\n"), None);
+    }
+}
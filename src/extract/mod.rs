@@ -0,0 +1,1164 @@
+pub mod checksum;
+pub mod conflict;
+pub mod layer;
+pub mod limits;
+pub mod metadata;
+pub mod special;
+pub mod symlink;
+pub mod verify;
+
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use tar::{Archive, Entry, EntryType};
+
+use crate::archive::{self, Output};
+use crate::error::Error;
+use crate::progress;
+use checksum::Checksums;
+use conflict::Conflict;
+use limits::{Limits, Usage};
+use metadata::{Metadata, Mtime};
+use special::SpecialFiles;
+use symlink::{Index, Symlinks};
+
+/// Options controls how the archive copied out of a container is written
+/// to the local filesystem.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    // Write the content to a single archive file instead of unpacking it
+    pub output: Option<Output>,
+    // Number of leading path components to remove from each entry
+    pub strip_components: usize,
+    // Place every file directly in the download path
+    pub flatten: bool,
+    // What to do with symbolic links found in the content
+    pub symlinks: Symlinks,
+    // What to do with files that already exist in the download path
+    pub on_conflict: Conflict,
+    // Fail if the download path already has content
+    pub require_empty: bool,
+    // Remove the existing content of the download path first
+    pub clean: bool,
+    // Restore the numeric owner recorded in the archive
+    pub preserve_owner: bool,
+    // Apply the user's umask to the permissions recorded in the archive
+    pub no_same_permissions: bool,
+    // Remove the setuid and setgid bits of extracted files
+    pub strip_setuid: bool,
+    // Restore the extended attributes recorded in the archive
+    pub preserve_xattrs: bool,
+    // Which modification time extracted files get
+    pub mtime: Mtime,
+    // Numeric uid and gid every extracted file should be owned by
+    pub chown: Option<(u32, u32)>,
+    // What to do with device nodes and FIFOs found in the content
+    pub special_files: SpecialFiles,
+    // Limits the content must stay within to be written at all
+    pub limits: Limits,
+    // Only report what would be written
+    pub dry_run: bool,
+    // Write a checksum manifest of the extracted files
+    pub checksums: Option<Checksums>,
+}
+
+/// Summary describes what happened while writing the content locally
+#[derive(Debug, Default)]
+pub struct Summary {
+    // Files, relative to the download path, that were written
+    pub files: Vec<PathBuf>,
+    // Paths, relative to the download path, that already existed
+    pub conflicts: Vec<PathBuf>,
+    // How the conflicting paths were handled
+    pub on_conflict: Conflict,
+    // Special files, relative to the download path, that were not created
+    pub skipped: Vec<PathBuf>,
+    // Nothing was written, the summary describes what would have been
+    pub dry_run: bool,
+    // Id of the container the content was copied out of
+    pub container_id: Option<String>,
+    // Warnings logged while writing the content
+    pub warnings: Vec<String>,
+    // Paths of the image deleted by the extracted layers, when extracting
+    // layers instead of the merged filesystem
+    pub whiteouts: Vec<PathBuf>,
+}
+
+impl Summary {
+    /// Logs the noteworthy parts of the summary
+    pub fn log(&self) {
+        if !self.whiteouts.is_empty() {
+            info!(
+                "📦 {} paths were deleted by the extracted layers:",
+                self.whiteouts.len()
+            );
+            for whiteout in &self.whiteouts {
+                info!("📦   {}", whiteout.display());
+            }
+        }
+
+        if self.dry_run {
+            info!(
+                "🔧 Dry run: {} files would be written to the download path:",
+                self.files.len()
+            );
+            for file in &self.files {
+                info!("🔧   {}", file.display());
+            }
+            if !self.conflicts.is_empty() {
+                info!(
+                    "🔧 Dry run: {} files already exist in the download path and would be {}:",
+                    self.conflicts.len(),
+                    self.on_conflict.action()
+                );
+                for conflict in &self.conflicts {
+                    info!("🔧   {}", conflict.display());
+                }
+            }
+            return;
+        }
+
+        if !self.conflicts.is_empty() {
+            warn!(
+                "⚠️ {} files already existed in the download path and were {}:",
+                self.conflicts.len(),
+                self.on_conflict.action()
+            );
+            for conflict in &self.conflicts {
+                warn!("⚠️   {}", conflict.display());
+            }
+        }
+        if !self.skipped.is_empty() {
+            warn!("⚠️ {} special files were skipped:", self.skipped.len());
+            for skipped in &self.skipped {
+                warn!("⚠️   {}", skipped.display());
+            }
+        }
+    }
+}
+
+/// Writes the tar archive copied out of a container locally. The archive is
+/// either re-encoded into the configured output file or unpacked into the
+/// download path.
+///
+/// # Arguments
+///
+/// * `bytes` - The tar archive copied out of the container
+/// * `content_path` - Where in the container filesystem the content was copied from
+/// * `download_path` - Where the archive should be unpacked
+/// * `opts` - Options controlling how the content is written
+pub fn extract(
+    bytes: &[u8],
+    content_path: &Path,
+    download_path: &Path,
+    opts: &Options,
+) -> Result<Summary> {
+    opts.limits.check(bytes)?;
+
+    if let Some(output) = &opts.output {
+        if opts.dry_run {
+            info!(
+                "🔧 Dry run: would write content to {:?} archive {}",
+                output.format,
+                output.path.display()
+            );
+            return Ok(Summary {
+                dry_run: true,
+                ..Default::default()
+            });
+        }
+        archive::write(bytes, output)?;
+        info!(
+            "✅ Wrote content to {:?} archive {} successfully",
+            output.format,
+            output.path.display()
+        );
+        return Ok(Summary::default());
+    }
+
+    if opts.require_empty {
+        conflict::ensure_empty(download_path)?;
+    }
+    if opts.dry_run {
+        let (files, mut conflicts) = Unpacker::new(download_path, opts).plan(bytes)?;
+        if opts.clean {
+            conflict::ensure_cleanable(download_path)?;
+            info!(
+                "🔧 Dry run: the existing content of {} would be removed",
+                download_path.display()
+            );
+            conflicts.clear();
+        }
+        if let Some(checksums) = &opts.checksums {
+            info!(
+                "🔧 Dry run: checksums would be written to {}",
+                download_path.join(checksums.algorithm.manifest()).display()
+            );
+        }
+        return Ok(Summary {
+            files,
+            conflicts,
+            on_conflict: opts.on_conflict,
+            dry_run: true,
+            ..Default::default()
+        });
+    }
+    if opts.clean {
+        conflict::clean(download_path)?;
+    }
+
+    let mut unpacker = Unpacker::new(download_path, opts);
+    unpacker.unpack(bytes, &archive_base(content_path))?;
+
+    info!(
+        "✅ Copied content to {} successfully",
+        download_path.display()
+    );
+
+    if let Some(checksums) = &opts.checksums {
+        let manifest = checksum::write(download_path, &unpacker.summary.files, checksums)?;
+        info!("✅ Wrote checksums to {}", manifest.display());
+    }
+    unpacker.summary.warnings = unpacker.warnings.into_inner();
+
+    Ok(unpacker.summary)
+}
+
+// Unpacker writes the entries of an archive into the download path one by
+// one, rewriting their paths according to the extract options.
+struct Unpacker<'a> {
+    root: &'a Path,
+    opts: &'a Options,
+    // Basenames already written when flattening
+    flattened: HashSet<OsString>,
+    // Archive index used to resolve symlinks, when the policy requires it
+    index: Option<Rc<Index>>,
+    // Bytes and entries written so far, charged to the limits when
+    // following symlinks copies content more than once
+    usage: Usage,
+    // Files written during this run, which never count as conflicts
+    written: HashSet<PathBuf>,
+    // Post-processing applied to every extracted path
+    metadata: Metadata,
+    // Warnings logged so far, kept for the summary
+    warnings: RefCell<Vec<String>>,
+    summary: Summary,
+}
+
+impl<'a> Unpacker<'a> {
+    fn new(root: &'a Path, opts: &'a Options) -> Self {
+        Unpacker {
+            root,
+            opts,
+            flattened: HashSet::new(),
+            index: None,
+            usage: Usage::default(),
+            written: HashSet::new(),
+            metadata: Metadata {
+                umask: opts.no_same_permissions.then(metadata::umask),
+                strip_setuid: opts.strip_setuid,
+                mtime: match opts.mtime {
+                    Mtime::Epoch(seconds) => Some(seconds),
+                    _ => None,
+                },
+                chown: opts.chown,
+            },
+            warnings: RefCell::new(Vec::new()),
+            summary: Summary {
+                on_conflict: opts.on_conflict,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn unpack(&mut self, bytes: &[u8], base: &Path) -> Result<()> {
+        fs::create_dir_all(self.root)?;
+
+        if matches!(
+            self.opts.symlinks,
+            Symlinks::RewriteRelative | Symlinks::Follow
+        ) {
+            let index = Index::build(bytes, base)?;
+            self.usage = Usage {
+                bytes: bytes.len() as u64,
+                entries: index.len() as u64,
+            };
+            self.index = Some(Rc::new(index));
+        }
+        if let (Symlinks::Follow, Some(index)) = (self.opts.symlinks, self.index.clone()) {
+            self.charge_followed(bytes, &index)?;
+        }
+
+        // Refuse to write anything if any of the content already exists
+        if self.opts.on_conflict == Conflict::Error {
+            let (_, conflicts) = Unpacker::new(self.root, self.opts).plan(bytes)?;
+            if !conflicts.is_empty() {
+                return Err(anyhow!(
+                    "{} files already exist in the download path: {}",
+                    conflicts.len(),
+                    list(&conflicts)
+                ));
+            }
+        }
+
+        let mut archive = Archive::new(bytes);
+        archive.set_preserve_ownerships(self.opts.preserve_owner);
+        // Modes are always the ones recorded in the archive, the umask and
+        // the setuid bits are then handled by the metadata settings
+        archive.set_preserve_permissions(true);
+        archive.set_unpack_xattrs(self.opts.preserve_xattrs);
+        archive.set_preserve_mtime(self.opts.mtime == Mtime::Archive);
+
+        // Directories are unpacked last so that their permissions and
+        // modification times are not changed by the files written into them.
+        let mut directories = Vec::new();
+        let bar = progress::files("Writing the content");
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            let is_dir = entry.header().entry_type() == EntryType::Directory;
+
+            let relative = match self.destination(&path, is_dir)? {
+                Some(relative) => relative,
+                None => {
+                    trace!("🔧 Skipping {}", path.display());
+                    continue;
+                }
+            };
+            let dest = self.root.join(&relative);
+            self.ensure_not_manifest(&relative)?;
+
+            if let Some(kind) = special::describe(entry.header().entry_type()) {
+                match self.opts.special_files {
+                    SpecialFiles::Skip => {
+                        debug!("🔧 Skipping {} {}", kind, path.display());
+                        self.summary.skipped.push(relative);
+                        continue;
+                    }
+                    SpecialFiles::Error => {
+                        return Err(anyhow!("refusing to extract {} {}", kind, path.display()))
+                    }
+                    SpecialFiles::Create => {}
+                }
+            }
+
+            if !self.resolve_conflict(&relative, &dest, is_dir)? {
+                debug!("🔧 Keeping existing {}", dest.display());
+                continue;
+            }
+
+            if is_dir {
+                self.create_dirs(&relative)?;
+                directories.push((entry, dest));
+                continue;
+            }
+
+            if let Some(parent) = relative.parent() {
+                self.create_dirs(parent)?;
+            }
+            self.unpack_entry(&mut entry, bytes, &path, &dest)?;
+            self.metadata.apply(&dest)?;
+            // Links may have been skipped by the symlink policy, and the
+            // files copied by following them are already listed
+            let followed = self.opts.symlinks == Symlinks::Follow
+                && entry.header().entry_type() == EntryType::Symlink;
+            if !followed && fs::symlink_metadata(&dest).is_ok() {
+                self.summary.files.push(relative);
+                bar.inc(1);
+            }
+        }
+
+        for (mut entry, dest) in directories.into_iter().rev() {
+            entry.unpack(&dest)?;
+            // tar only restores the modification time of files
+            if self.opts.mtime == Mtime::Archive {
+                let mtime = filetime::FileTime::from_unix_time(entry.header().mtime()? as i64, 0);
+                filetime::set_file_mtime(&dest, mtime)?;
+            }
+            self.metadata.apply(&dest)?;
+        }
+
+        Ok(())
+    }
+
+    // unpack_entry writes a single non-directory entry to dest. Hard links are
+    // resolved against the rewritten paths of their targets.
+    fn unpack_entry<R: Read>(
+        &mut self,
+        entry: &mut Entry<R>,
+        bytes: &[u8],
+        path: &Path,
+        dest: &Path,
+    ) -> Result<()> {
+        if entry.header().entry_type() == EntryType::Symlink {
+            return self.unpack_symlink(entry, bytes, path, dest);
+        }
+
+        if special::describe(entry.header().entry_type()).is_some() {
+            remove_existing(dest)?;
+            return special::create(entry.header(), dest);
+        }
+
+        if entry.header().entry_type() == EntryType::Link {
+            let target = match entry.link_name()? {
+                Some(target) => target.into_owned(),
+                None => return Err(anyhow!("hard link {} has no target", dest.display())),
+            };
+            let target = match self.rewrite(&target, false)? {
+                Some(target) => self.root.join(target),
+                None => {
+                    return Err(anyhow!(
+                        "hard link {} points to {} which is not extracted",
+                        dest.display(),
+                        target.display()
+                    ))
+                }
+            };
+            // The target may be reached through a symlink pointing outside of
+            // the download path, linking it would expose a file of the host
+            let target = fs::canonicalize(&target)?;
+            self.ensure_inside(&target)?;
+            remove_existing(dest)?;
+            fs::hard_link(target, dest)?;
+            return Ok(());
+        }
+
+        entry.unpack(dest)?;
+        Ok(())
+    }
+
+    // unpack_symlink writes a symlink entry to dest according to the
+    // configured symlink policy.
+    fn unpack_symlink<R: Read>(
+        &mut self,
+        entry: &mut Entry<R>,
+        bytes: &[u8],
+        path: &Path,
+        dest: &Path,
+    ) -> Result<()> {
+        let path: PathBuf = normalize(path)?.iter().collect();
+        let link = match entry.link_name()? {
+            Some(link) => link.into_owned(),
+            None => return Err(anyhow!("symlink {} has no target", path.display())),
+        };
+
+        match (self.opts.symlinks, self.index.clone()) {
+            (Symlinks::RewriteRelative, Some(index)) => {
+                match self.rewrite_link(&index, &path, &link)? {
+                    Some(relative) => {
+                        trace!(
+                            "🔧 Rewriting symlink {} to {}",
+                            path.display(),
+                            relative.display()
+                        );
+                        remove_existing(dest)?;
+                        symlink::symlink(&relative, dest)?;
+                    }
+                    None => self.warn(format!(
+                        "Skipping symlink {} as {} is outside of the extracted content",
+                        path.display(),
+                        link.display()
+                    )),
+                }
+            }
+            (Symlinks::Follow, Some(index)) => match index.canonical(&path) {
+                Some(target) if path.starts_with(&target) => self.warn(format!(
+                    "Skipping symlink {} as it points to one of its parents",
+                    path.display()
+                )),
+                Some(target) => {
+                    self.copy_indexed(&index, bytes, &target, dest, &mut HashSet::new())?
+                }
+                None => self.warn(format!(
+                    "Skipping symlink {} as {} cannot be resolved inside the copied content",
+                    path.display(),
+                    link.display()
+                )),
+            },
+            (Symlinks::Skip, _) => debug!("🔧 Skipping symlink {}", path.display()),
+            _ => {
+                entry.unpack(dest)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // rewrite_link returns the relative link written for the symlink at the
+    // normalized archive path, or None if its target is not extracted
+    fn rewrite_link(&self, index: &Index, path: &Path, link: &Path) -> Result<Option<PathBuf>> {
+        let target = index.target(path, link).and_then(|target| {
+            let is_dir = matches!(index.get(&target), Some(e) if e.kind == EntryType::Directory);
+            self.rewrite(&target, is_dir).ok().flatten()
+        });
+        Ok(match (target, self.rewrite(path, false)?) {
+            (Some(target), Some(from)) => {
+                let from = from.parent().unwrap_or_else(|| Path::new(""));
+                Some(symlink::relative(from, &target))
+            }
+            _ => None,
+        })
+    }
+
+    // charge_followed checks what following the symlinks of the archive
+    // copies against the limits before anything is written, so that a
+    // breach never leaves partial output
+    fn charge_followed(&self, bytes: &[u8], index: &Index) -> Result<()> {
+        let mut usage = Usage {
+            bytes: self.usage.bytes,
+            entries: self.usage.entries,
+        };
+        let mut archive = Archive::new(bytes);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if entry.header().entry_type() != EntryType::Symlink {
+                continue;
+            }
+            let path: PathBuf = normalize(&entry.path()?)?.iter().collect();
+            let relative = match self.rewrite(&path, false)? {
+                Some(relative) => relative,
+                None => continue,
+            };
+            match index.canonical(&path) {
+                Some(target) if !path.starts_with(&target) => {
+                    index.expand(&target, &relative, &mut |entry, dest| {
+                        let size = match entry.kind {
+                            EntryType::Directory => 0,
+                            _ => entry.size,
+                        };
+                        Ok(self.opts.limits.charge(&mut usage, dest, size)?)
+                    })?
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // copy_indexed writes the content of the archive entry at src to dest,
+    // copying directories recursively. Used to follow symlinks. visiting
+    // holds the canonical directories being copied, to break cycles of
+    // links, and every copied entry is charged to the limits.
+    fn copy_indexed(
+        &mut self,
+        index: &Index,
+        bytes: &[u8],
+        src: &Path,
+        dest: &Path,
+        visiting: &mut HashSet<PathBuf>,
+    ) -> Result<()> {
+        let entry = match index.get(src) {
+            Some(entry) => entry,
+            None => {
+                self.warn(format!(
+                    "Skipping {} as {} is not in the copied content",
+                    dest.display(),
+                    src.display()
+                ));
+                return Ok(());
+            }
+        };
+        let relative = dest.strip_prefix(self.root)?.to_path_buf();
+        self.ensure_not_manifest(&relative)?;
+
+        match entry.kind {
+            EntryType::Regular | EntryType::Continuous => {
+                self.opts
+                    .limits
+                    .charge(&mut self.usage, &relative, entry.size)?;
+                if !self.resolve_conflict(&relative, dest, false)? {
+                    debug!("🔧 Keeping existing {}", dest.display());
+                    return Ok(());
+                }
+                let start = entry.position as usize;
+                let end = start + entry.size as usize;
+                remove_existing(dest)?;
+                fs::write(dest, &bytes[start..end])?;
+                set_mode(dest, entry.mode)?;
+                self.metadata.apply(dest)?;
+                self.summary.files.push(relative);
+            }
+            EntryType::Directory => {
+                self.opts.limits.charge(&mut self.usage, &relative, 0)?;
+                if !self.resolve_conflict(&relative, dest, true)? {
+                    debug!("🔧 Keeping existing {}", dest.display());
+                    return Ok(());
+                }
+                self.create_dirs(&relative)?;
+                visiting.insert(src.to_path_buf());
+                for child in index.children(src) {
+                    let name = child.file_name().unwrap_or_default();
+                    match index.canonical(child) {
+                        Some(target) if visiting.contains(&target) => self.warn(format!(
+                            "Skipping symlink {} as it points to one of its parents",
+                            child.display()
+                        )),
+                        Some(target) => {
+                            self.copy_indexed(index, bytes, &target, &dest.join(name), visiting)?
+                        }
+                        None => self.warn(format!(
+                            "Skipping {} as it cannot be resolved inside the copied content",
+                            child.display()
+                        )),
+                    }
+                }
+                visiting.remove(src);
+            }
+            EntryType::Link => match entry.link.as_deref().and_then(symlink::lexical) {
+                Some(target) => self.copy_indexed(index, bytes, &target, dest, visiting)?,
+                None => self.warn(format!("Skipping hard link {}", src.display())),
+            },
+            other => self.warn(format!(
+                "Skipping {} as {:?} entries cannot be followed",
+                src.display(),
+                other
+            )),
+        }
+
+        Ok(())
+    }
+
+    // ensure_not_manifest refuses content that would be replaced by the
+    // checksum manifest written at the end of the run
+    fn ensure_not_manifest(&self, relative: &Path) -> Result<()> {
+        match &self.opts.checksums {
+            Some(checksums) if relative == Path::new(checksums.algorithm.manifest()) => {
+                Err(anyhow!(
+                    "the content has a {} file, which --checksums would overwrite",
+                    relative.display()
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // warn logs a warning and keeps it for the summary
+    fn warn(&self, message: String) {
+        warn!("⚠️ {}", message);
+        self.warnings.borrow_mut().push(message);
+    }
+
+    // resolve_conflict applies the conflict policy when dest already exists
+    // and returns whether the entry should be written.
+    fn resolve_conflict(&mut self, relative: &Path, dest: &Path, is_dir: bool) -> Result<bool> {
+        let existing = match fs::symlink_metadata(dest) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                self.written.insert(dest.to_path_buf());
+                return Ok(true);
+            }
+        };
+        if self.written.contains(dest) || (is_dir && existing.is_dir()) {
+            return Ok(true);
+        }
+
+        self.summary.conflicts.push(relative.to_path_buf());
+        match self.opts.on_conflict {
+            Conflict::Overwrite if existing.is_dir() => Err(anyhow!(
+                "cannot overwrite directory {} with a file",
+                dest.display()
+            )),
+            Conflict::Overwrite => {
+                if is_dir {
+                    fs::remove_file(dest)?;
+                }
+                self.written.insert(dest.to_path_buf());
+                Ok(true)
+            }
+            Conflict::Skip if is_dir => Err(anyhow!(
+                "cannot extract directory {} as a file with the same name exists",
+                dest.display()
+            )),
+            Conflict::Skip => Ok(false),
+            Conflict::Error => Err(anyhow!(
+                "{} already exists in the download path",
+                relative.display()
+            )),
+            Conflict::Backup => {
+                let backup = conflict::backup(dest)?;
+                debug!("🔧 Backed up {} to {}", dest.display(), backup.display());
+                self.written.insert(dest.to_path_buf());
+                Ok(true)
+            }
+        }
+    }
+
+    // plan lists the files of the archive that would be written and the
+    // entries that already exist in the download path, without writing
+    // anything.
+    fn plan(&mut self, bytes: &[u8]) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+        let mut files = Vec::new();
+        let mut conflicts = Vec::new();
+        let mut seen = HashSet::new();
+
+        let mut archive = Archive::new(bytes);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = entry.path()?.into_owned();
+            let is_dir = entry.header().entry_type() == EntryType::Directory;
+            if special::describe(entry.header().entry_type()).is_some()
+                && self.opts.special_files == SpecialFiles::Skip
+            {
+                continue;
+            }
+            if entry.header().entry_type() == EntryType::Symlink
+                && self.opts.symlinks == Symlinks::Skip
+            {
+                continue;
+            }
+
+            let relative = match self.destination(&path, is_dir)? {
+                Some(relative) => relative,
+                None => continue,
+            };
+            if !seen.insert(relative.clone()) {
+                continue;
+            }
+            match fs::symlink_metadata(self.root.join(&relative)) {
+                Ok(metadata) if is_dir && metadata.is_dir() => {}
+                Ok(_) => conflicts.push(relative.clone()),
+                Err(_) => {}
+            }
+            if !is_dir {
+                files.push(relative);
+            }
+        }
+
+        Ok((files, conflicts))
+    }
+
+    // create_dirs creates the directories of a path relative to the download
+    // path one level at a time, checking that each level resolves inside of
+    // the download path before anything is created in it.
+    fn create_dirs(&self, relative: &Path) -> Result<()> {
+        let mut dir = self.root.to_path_buf();
+        for component in relative.components() {
+            dir.push(component);
+            if fs::symlink_metadata(&dir).is_err() {
+                match fs::create_dir(&dir) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            self.ensure_inside(&dir)?;
+        }
+        Ok(())
+    }
+
+    // ensure_inside refuses to write through paths that resolve outside of
+    // the download path, such as symlinks pointing to absolute paths.
+    fn ensure_inside(&self, dir: &Path) -> Result<()> {
+        let root = fs::canonicalize(self.root)?;
+        if !fs::canonicalize(dir)?.starts_with(root) {
+            return Err(Error::UnpackEscape(format!(
+                "trying to unpack {} outside of destination path",
+                dir.display()
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    // destination returns the path relative to the download path an entry
+    // should be written to, or None if the entry should be skipped.
+    fn destination(&mut self, path: &Path, is_dir: bool) -> Result<Option<PathBuf>> {
+        let relative = match self.rewrite(path, is_dir)? {
+            Some(relative) => relative,
+            None => return Ok(None),
+        };
+
+        if self.opts.flatten {
+            let name = relative.clone().into_os_string();
+            if !self.flattened.insert(name) {
+                return Err(anyhow!(
+                    "cannot flatten {}: a file named {} was already extracted",
+                    path.display(),
+                    relative.display()
+                ));
+            }
+        }
+
+        Ok(Some(relative))
+    }
+
+    // rewrite applies --strip-components and --flatten to an archive path
+    fn rewrite(&self, path: &Path, is_dir: bool) -> Result<Option<PathBuf>> {
+        let components = normalize(path)?;
+        if components.len() <= self.opts.strip_components {
+            return Ok(None);
+        }
+        let components = &components[self.opts.strip_components..];
+
+        if self.opts.flatten {
+            if is_dir {
+                return Ok(None);
+            }
+            return Ok(components.last().map(PathBuf::from));
+        }
+
+        Ok(Some(components.iter().collect()))
+    }
+}
+
+// list joins the first few paths for error messages
+fn list(paths: &[PathBuf]) -> String {
+    let mut list: Vec<String> = paths
+        .iter()
+        .take(10)
+        .map(|path| path.display().to_string())
+        .collect();
+    if paths.len() > 10 {
+        list.push(format!("and {} more", paths.len() - 10));
+    }
+    list.join(", ")
+}
+
+// archive_base returns the directory of the image the archive paths are
+// relative to: the one containing the content path, which is where absolute
+// links found in the content are resolved from.
+fn archive_base(content_path: &Path) -> PathBuf {
+    let base = Path::new("/").join(content_path);
+    base.parent()
+        .unwrap_or_else(|| Path::new("/"))
+        .to_path_buf()
+}
+
+// remove_existing removes the file or link at dest, so that it can be
+// replaced without writing through an existing symlink.
+fn remove_existing(dest: &Path) -> Result<()> {
+    if fs::symlink_metadata(dest).is_ok() {
+        fs::remove_file(dest)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(dest: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(dest, fs::Permissions::from_mode(mode & 0o7777))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_dest: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+// normalize returns the plain components of an archive path, refusing paths
+// that would be written outside of the download path.
+fn normalize(path: &Path) -> Result<Vec<OsString>> {
+    let mut components = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => components.push(part.to_os_string()),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            Component::ParentDir => {
+                return Err(Error::UnpackEscape(format!(
+                    "trying to unpack {} outside of destination path",
+                    path.display()
+                ))
+                .into())
+            }
+        }
+    }
+    Ok(components)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract, Options, Unpacker};
+    use crate::extract::checksum::{Algorithm, Checksums, Style};
+    use crate::extract::limits::{LimitExceeded, Limits};
+    use crate::extract::metadata::Mtime;
+    use crate::extract::symlink::Symlinks;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_dirs_outside() {
+        let dir = std::env::temp_dir().join(format!("dcp-dirs-{}", std::process::id()));
+        let outside = dir.join("outside");
+        fs::create_dir_all(&outside).unwrap();
+
+        // root/a/created/deep/file would be written through root/a
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "root/a", &outside)
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "root/a/created/deep/file", &b"data"[..])
+            .unwrap();
+        let bytes = builder.into_inner().unwrap();
+
+        let result = extract(
+            &bytes,
+            Path::new("/root"),
+            &dir.join("out"),
+            &Options::default(),
+        );
+        let created = outside.join("created").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+        assert!(!created);
+    }
+
+    #[test]
+    fn test_hard_link_outside() {
+        let dir = std::env::temp_dir().join(format!("dcp-hard-link-{}", std::process::id()));
+        let outside = dir.join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret"), "secret").unwrap();
+        let mtime = fs::metadata(outside.join("secret"))
+            .unwrap()
+            .modified()
+            .unwrap();
+
+        // root/h is a hard link to a file of the host, reached through root/s
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "root/s", &outside)
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "root/h", "root/s/secret")
+            .unwrap();
+        let bytes = builder.into_inner().unwrap();
+
+        let opts = Options {
+            mtime: Mtime::Epoch(0),
+            ..Default::default()
+        };
+        let result = extract(&bytes, Path::new("/root"), &dir.join("out"), &opts);
+        let modified = fs::metadata(outside.join("secret"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(modified, mtime);
+    }
+
+    // follow_archive builds an archive of root/a and root/b linking to each
+    // other, with a file in each
+    fn follow_archive() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for dir in ["root/a", "root/b"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            header.set_mode(0o755);
+            builder
+                .append_data(&mut header, dir, std::io::empty())
+                .unwrap();
+            let mut header = tar::Header::new_gnu();
+            header.set_size(4);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, format!("{}/file", dir), &b"data"[..])
+                .unwrap();
+        }
+        for (path, link) in [("root/a/x", "/root/b"), ("root/b/y", "/root/a")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            builder.append_link(&mut header, path, link).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_follow_cycle() {
+        let dir = std::env::temp_dir().join(format!("dcp-follow-cycle-{}", std::process::id()));
+        let opts = Options {
+            symlinks: Symlinks::Follow,
+            ..Default::default()
+        };
+        let result = extract(&follow_archive(), Path::new("/root"), &dir, &opts);
+        // root/a/x/y is root/a again, whose link back to root/b is skipped
+        let copied = fs::read(dir.join("root/a/x/y/file")).ok();
+        let nested = dir.join("root/a/x/y/x").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_ok(), "{:?}", result.err());
+        assert_eq!(copied.as_deref(), Some(&b"data"[..]));
+        assert!(!nested);
+    }
+
+    #[test]
+    fn test_follow_limits() {
+        let dir = std::env::temp_dir().join(format!("dcp-follow-limits-{}", std::process::id()));
+        let bytes = follow_archive();
+        // The archive itself has 6 entries, following the links copies more
+        let opts = Options {
+            symlinks: Symlinks::Follow,
+            limits: Limits {
+                max_entries: Some(6),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = extract(&bytes, Path::new("/root"), &dir, &opts);
+        // The breach is found before anything is written
+        let written = dir.join("root").exists();
+        let _ = fs::remove_dir_all(&dir);
+
+        let e = result.unwrap_err();
+        assert!(e.downcast_ref::<LimitExceeded>().is_some(), "{:?}", e);
+        assert!(!written);
+    }
+
+    // metadata_archive returns an archive with a setuid file and a directory
+    // modified at 1000s since the epoch
+    fn metadata_archive() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o750);
+        header.set_mtime(1000);
+        builder
+            .append_data(&mut header, "root/bin", &b""[..])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o4755);
+        header.set_mtime(1000);
+        builder
+            .append_data(&mut header, "root/bin/tool", &b"tool"[..])
+            .unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_metadata() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = std::env::temp_dir().join(format!("dcp-metadata-{}", std::process::id()));
+        let bytes = metadata_archive();
+        let stat = |path: &str| fs::metadata(dir.join(path)).unwrap();
+        let mode = |path: &str| stat(path).permissions().mode() & 0o7777;
+
+        // Modes and times of the archive are kept without --preserve-owner
+        extract(&bytes, Path::new("/root"), &dir, &Options::default()).unwrap();
+        let kept = (
+            mode("root/bin"),
+            mode("root/bin/tool"),
+            stat("root/bin/tool").mtime(),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let opts = Options {
+            no_same_permissions: true,
+            strip_setuid: true,
+            mtime: Mtime::Epoch(0),
+            chown: Some((uid, gid)),
+            ..Default::default()
+        };
+        extract(&bytes, Path::new("/root"), &dir, &opts).unwrap();
+        let umask = super::metadata::umask();
+        let adjusted = (
+            mode("root/bin"),
+            mode("root/bin/tool"),
+            stat("root/bin/tool").mtime(),
+        );
+        let owner = (stat("root/bin/tool").uid(), stat("root/bin/tool").gid());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(kept, (0o750, 0o4755, 1000));
+        assert_eq!(adjusted, (0o750 & !umask, 0o755 & !umask, 0));
+        assert_eq!(owner, (uid, gid));
+    }
+
+    #[test]
+    fn test_content_manifest() {
+        let dir = std::env::temp_dir().join(format!("dcp-manifest-{}", std::process::id()));
+
+        // The image ships a link where the checksum manifest is written
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "root/SHA256SUMS", "/etc/passwd")
+            .unwrap();
+        let bytes = builder.into_inner().unwrap();
+
+        let opts = Options {
+            strip_components: 1,
+            checksums: Some(Checksums {
+                algorithm: Algorithm::Sha256,
+                style: Style::Gnu,
+            }),
+            ..Default::default()
+        };
+        let result = extract(&bytes, Path::new("/root"), &dir, &opts);
+        let written = fs::symlink_metadata(dir.join("SHA256SUMS")).is_ok();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(result.is_err());
+        assert!(!written);
+    }
+
+    #[test]
+    fn test_destination() {
+        let root = Path::new("out");
+        let opts = Options {
+            strip_components: 1,
+            ..Default::default()
+        };
+        let mut unpacker = Unpacker::new(root, &opts);
+        assert_eq!(
+            unpacker.destination(Path::new("manifests"), true).unwrap(),
+            None
+        );
+        assert_eq!(
+            unpacker
+                .destination(Path::new("./manifests/crds/a.yaml"), false)
+                .unwrap(),
+            Some(PathBuf::from("crds/a.yaml"))
+        );
+        assert!(unpacker.destination(Path::new("../a.yaml"), false).is_err());
+
+        let opts = Options {
+            flatten: true,
+            ..Default::default()
+        };
+        let mut unpacker = Unpacker::new(root, &opts);
+        assert_eq!(
+            unpacker
+                .destination(Path::new("manifests/crds"), true)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            unpacker
+                .destination(Path::new("manifests/crds/a.yaml"), false)
+                .unwrap(),
+            Some(PathBuf::from("a.yaml"))
+        );
+        assert!(unpacker
+            .destination(Path::new("manifests/a.yaml"), false)
+            .is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use std::str::FromStr;
+use tar::{EntryType, Header};
+
+/// Special file policies accepted by `--special-files`
+pub const POLICIES: &[&str] = &["skip", "error", "create"];
+
+/// SpecialFiles decides what happens to device nodes and FIFOs found in the
+/// copied content.
+///
+/// * `Skip` - Do not create them. Skipped entries are listed in the summary.
+/// * `Error` - Fail the extraction.
+/// * `Create` - Create them, which requires privileges for device nodes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialFiles {
+    #[default]
+    Skip,
+    Error,
+    Create,
+}
+
+impl FromStr for SpecialFiles {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(SpecialFiles::Skip),
+            "error" => Ok(SpecialFiles::Error),
+            "create" => Ok(SpecialFiles::Create),
+            _ => Err(anyhow!("unsupported special file policy {}", s)),
+        }
+    }
+}
+
+// describe returns a human readable name for special entry types, or None
+// for the entry types that are not special files.
+pub(super) fn describe(kind: EntryType) -> Option<&'static str> {
+    match kind {
+        EntryType::Char => Some("character device"),
+        EntryType::Block => Some("block device"),
+        EntryType::Fifo => Some("FIFO"),
+        _ => None,
+    }
+}
+
+// create makes the device node or FIFO described by header at dest. The tar
+// crate would write these entries as empty regular files.
+#[cfg(unix)]
+pub(super) fn create(header: &Header, dest: &Path) -> Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = CString::new(dest.as_os_str().as_bytes())?;
+    let mode = (header.mode()? & 0o7777) as libc::mode_t;
+    let kind = match header.entry_type() {
+        EntryType::Char => libc::S_IFCHR,
+        EntryType::Block => libc::S_IFBLK,
+        _ => libc::S_IFIFO,
+    };
+    // FIFOs are not required to record device numbers
+    let major = header.device_major().ok().flatten().unwrap_or(0);
+    let minor = header.device_minor().ok().flatten().unwrap_or(0);
+
+    let result = unsafe {
+        libc::mknod(
+            path.as_ptr(),
+            kind | mode,
+            libc::makedev(major as _, minor as _),
+        )
+    };
+    if result != 0 {
+        return Err(anyhow!(
+            "failed to create {}: {}",
+            dest.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    // mknod(2) applies the umask, restore the mode recorded in the archive.
+    // The setuid and setgid bits are stripped later on with the metadata of
+    // the other files.
+    std::fs::set_permissions(dest, std::fs::Permissions::from_mode(mode as u32))?;
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(super) fn create(_header: &Header, dest: &Path) -> Result<()> {
+    Err(anyhow!(
+        "cannot create special file {} on this platform",
+        dest.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpecialFiles;
+    use crate::extract::{extract, Options};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // special_archive returns an archive with a regular file, a FIFO and a
+    // character device
+    fn special_archive(devices: bool) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "root/dev/file", &b"data"[..])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Fifo);
+        header.set_size(0);
+        header.set_mode(0o600);
+        builder
+            .append_data(&mut header, "root/dev/fifo", &b""[..])
+            .unwrap();
+        if devices {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Char);
+            header.set_size(0);
+            header.set_mode(0o666);
+            header.set_device_major(1).unwrap();
+            header.set_device_minor(3).unwrap();
+            builder
+                .append_data(&mut header, "root/dev/null", &b""[..])
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn options(special_files: SpecialFiles) -> Options {
+        Options {
+            special_files,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_skip() {
+        let dir = std::env::temp_dir().join(format!("dcp-special-skip-{}", std::process::id()));
+        let summary = extract(
+            &special_archive(true),
+            Path::new("/root"),
+            &dir,
+            &options(SpecialFiles::Skip),
+        );
+        let file = dir.join("root/dev/file").is_file();
+        let special = ["fifo", "null"].map(|name| dir.join("root/dev").join(name).exists());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let summary = summary.unwrap();
+        assert_eq!(
+            summary.skipped,
+            vec![
+                PathBuf::from("root/dev/fifo"),
+                PathBuf::from("root/dev/null")
+            ]
+        );
+        assert!(file);
+        assert_eq!(special, [false, false]);
+    }
+
+    #[test]
+    fn test_error() {
+        let dir = std::env::temp_dir().join(format!("dcp-special-error-{}", std::process::id()));
+        let result = extract(
+            &special_archive(false),
+            Path::new("/root"),
+            &dir,
+            &options(SpecialFiles::Error),
+        );
+        let _ = fs::remove_dir_all(&dir);
+
+        let e = result.unwrap_err();
+        assert!(e.to_string().contains("FIFO root/dev/fifo"), "{}", e);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_create() {
+        use std::os::unix::fs::FileTypeExt;
+
+        let dir = std::env::temp_dir().join(format!("dcp-special-create-{}", std::process::id()));
+        let fifo = extract(
+            &special_archive(false),
+            Path::new("/root"),
+            &dir,
+            &options(SpecialFiles::Create),
+        );
+        let is_fifo =
+            fs::symlink_metadata(dir.join("root/dev/fifo")).map(|m| m.file_type().is_fifo());
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Device nodes can only be created with privileges
+        let device = extract(
+            &special_archive(true),
+            Path::new("/root"),
+            &dir,
+            &options(SpecialFiles::Create),
+        );
+        let is_device =
+            fs::symlink_metadata(dir.join("root/dev/null")).map(|m| m.file_type().is_char_device());
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(fifo.is_ok(), "{:?}", fifo);
+        assert!(is_fifo.unwrap());
+        match device {
+            Ok(_) => assert!(is_device.unwrap()),
+            Err(e) => assert!(e.to_string().contains("failed to create"), "{}", e),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_create_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, mode) in [("setuid", 0o4644), ("sticky", 0o1644)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Fifo);
+            header.set_size(0);
+            header.set_mode(mode);
+            builder
+                .append_data(&mut header, format!("root/dev/{}", name), &b""[..])
+                .unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("dcp-special-mode-{}", std::process::id()));
+        let mut modes = Vec::new();
+        for strip_setuid in [false, true] {
+            let opts = Options {
+                strip_setuid,
+                ..options(SpecialFiles::Create)
+            };
+            extract(&archive, Path::new("/root"), &dir, &opts).unwrap();
+            for name in ["setuid", "sticky"] {
+                let metadata = fs::symlink_metadata(dir.join("root/dev").join(name)).unwrap();
+                modes.push(metadata.permissions().mode() & 0o7777);
+            }
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        // The special bits are only stripped with --strip-setuid
+        assert_eq!(modes, vec![0o4644, 0o1644, 0o644, 0o1644]);
+    }
+}
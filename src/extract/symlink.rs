@@ -0,0 +1,323 @@
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use tar::{Archive, EntryType};
+
+/// Symlink policies accepted by `--symlinks`
+pub const POLICIES: &[&str] = &["preserve", "rewrite-relative", "follow", "skip"];
+
+// Maximum number of symlinks followed while resolving a single path
+const MAX_HOPS: usize = 40;
+
+/// Symlinks decides what happens to symbolic links found in the copied content.
+///
+/// * `Preserve` - Write the link as found in the image. Nothing is ever written through
+///   a link that points outside of the download path.
+/// * `RewriteRelative` - Rewrite absolute links to relative links within the download path.
+///   Links pointing outside of the extracted content are skipped.
+/// * `Follow` - Resolve the link inside the image and write the content it points to.
+/// * `Skip` - Do not write links at all.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Symlinks {
+    #[default]
+    Preserve,
+    RewriteRelative,
+    Follow,
+    Skip,
+}
+
+impl FromStr for Symlinks {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "preserve" => Ok(Symlinks::Preserve),
+            "rewrite-relative" => Ok(Symlinks::RewriteRelative),
+            "follow" => Ok(Symlinks::Follow),
+            "skip" => Ok(Symlinks::Skip),
+            _ => Err(anyhow!("unsupported symlink policy {}", s)),
+        }
+    }
+}
+
+// Indexed is the metadata of an archive entry needed to resolve links and
+// read its content without iterating the archive again.
+pub(super) struct Indexed {
+    pub kind: EntryType,
+    pub link: Option<PathBuf>,
+    pub position: u64,
+    pub size: u64,
+    pub mode: u32,
+}
+
+// Index maps the normalized path of every archive entry to its metadata.
+// `base` is the directory of the image the archive paths are relative to.
+pub(super) struct Index {
+    base: PathBuf,
+    entries: HashMap<PathBuf, Indexed>,
+}
+
+impl Index {
+    pub fn build(bytes: &[u8], base: &Path) -> Result<Index> {
+        let mut entries = HashMap::new();
+        let mut archive = Archive::new(bytes);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = match lexical(&entry.path()?) {
+                Some(path) => path,
+                None => continue,
+            };
+            entries.insert(
+                path,
+                Indexed {
+                    kind: entry.header().entry_type(),
+                    link: entry.link_name()?.map(|link| link.into_owned()),
+                    position: entry.raw_file_position(),
+                    size: entry.size(),
+                    mode: entry.header().mode().unwrap_or(0o644),
+                },
+            );
+        }
+
+        Ok(Index {
+            base: base.to_path_buf(),
+            entries,
+        })
+    }
+
+    // len returns the number of entries in the archive
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Indexed> {
+        self.entries.get(path)
+    }
+
+    // children returns the entries directly inside dir, sorted by path
+    pub fn children(&self, dir: &Path) -> Vec<&PathBuf> {
+        let mut children: Vec<&PathBuf> = self
+            .entries
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .collect();
+        children.sort();
+        children
+    }
+
+    // target returns the archive path a link found at link points to, without
+    // resolving any further links. None is returned for targets outside of the
+    // copied content.
+    pub fn target(&self, link: &Path, target: &Path) -> Option<PathBuf> {
+        if target.has_root() {
+            return lexical(target.strip_prefix(&self.base).ok()?);
+        }
+        lexical(&link.parent().unwrap_or_else(|| Path::new("")).join(target))
+    }
+
+    // canonical resolves every link along path, like realpath(3) does on a
+    // filesystem. None is returned if the path leaves the copied content or
+    // too many links are followed.
+    pub fn canonical(&self, path: &Path) -> Option<PathBuf> {
+        let mut pending = parts(path);
+        pending.reverse();
+        let mut resolved = PathBuf::new();
+        let mut hops = 0;
+
+        while let Some(part) = pending.pop() {
+            if part == ".." {
+                if !resolved.pop() {
+                    return None;
+                }
+                continue;
+            }
+
+            let candidate = resolved.join(&part);
+            let link = match self.entries.get(&candidate) {
+                Some(entry) if entry.kind == EntryType::Symlink => entry.link.as_ref()?,
+                _ => {
+                    resolved = candidate;
+                    continue;
+                }
+            };
+
+            hops += 1;
+            if hops > MAX_HOPS {
+                return None;
+            }
+            let next = if link.has_root() {
+                resolved = PathBuf::new();
+                parts(link.strip_prefix(&self.base).ok()?)
+            } else {
+                parts(link)
+            };
+            pending.extend(next.into_iter().rev());
+        }
+
+        Some(resolved)
+    }
+
+    // expand calls visit with every regular file and directory that following
+    // a link to src copies, and the path it is copied to under dest, the same
+    // way the unpacker writes them. Links to one of their parents are skipped.
+    pub fn expand(
+        &self,
+        src: &Path,
+        dest: &Path,
+        visit: &mut dyn FnMut(&Indexed, &Path) -> Result<()>,
+    ) -> Result<()> {
+        self.expand_within(src, dest, &mut HashSet::new(), visit)
+    }
+
+    fn expand_within(
+        &self,
+        src: &Path,
+        dest: &Path,
+        visiting: &mut HashSet<PathBuf>,
+        visit: &mut dyn FnMut(&Indexed, &Path) -> Result<()>,
+    ) -> Result<()> {
+        let entry = match self.get(src) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        match entry.kind {
+            EntryType::Regular | EntryType::Continuous => visit(entry, dest)?,
+            EntryType::Directory => {
+                visit(entry, dest)?;
+                visiting.insert(src.to_path_buf());
+                for child in self.children(src) {
+                    let name = child.file_name().unwrap_or_default();
+                    match self.canonical(child) {
+                        Some(target) if !visiting.contains(&target) => {
+                            self.expand_within(&target, &dest.join(name), visiting, visit)?
+                        }
+                        _ => {}
+                    }
+                }
+                visiting.remove(src);
+            }
+            EntryType::Link => {
+                if let Some(target) = entry.link.as_deref().and_then(lexical) {
+                    self.expand_within(&target, dest, visiting, visit)?
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+// relative returns the path leading from the directory from to to, where
+// both are relative to the same root.
+pub(super) fn relative(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in &to[common..] {
+        path.push(component);
+    }
+    if path.as_os_str().is_empty() {
+        path.push(".");
+    }
+    path
+}
+
+// lexical normalizes a path without touching the filesystem. None is
+// returned if the path climbs above its root.
+pub(super) fn lexical(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for part in parts(path) {
+        if part == ".." {
+            if !normalized.pop() {
+                return None;
+            }
+        } else {
+            normalized.push(part);
+        }
+    }
+    Some(normalized)
+}
+
+// parts splits a path into its named components, keeping ".." so that it can
+// be resolved against the components before it.
+fn parts(path: &Path) -> Vec<OsString> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_os_string()),
+            Component::ParentDir => Some(OsString::from("..")),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+pub(super) fn symlink(target: &Path, dest: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, dest)
+}
+
+#[cfg(windows)]
+pub(super) fn symlink(target: &Path, dest: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{relative, Index};
+    use std::path::{Path, PathBuf};
+
+    fn index() -> Index {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header.clone(), "lib", "usr/lib")
+            .unwrap();
+        builder
+            .append_link(&mut header.clone(), "etc/alternatives/ld", "/lib/ld.so")
+            .unwrap();
+        builder.append_link(&mut header, "loop", "/loop").unwrap();
+        Index::build(&builder.into_inner().unwrap(), Path::new("/")).unwrap()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let index = index();
+        assert_eq!(
+            index.target(Path::new("etc/alternatives/ld"), Path::new("/lib/ld.so")),
+            Some(PathBuf::from("lib/ld.so"))
+        );
+        assert_eq!(
+            index.target(Path::new("etc/passwd"), Path::new("../../../etc/shadow")),
+            None
+        );
+        assert_eq!(
+            index.canonical(Path::new("etc/alternatives/ld")),
+            Some(PathBuf::from("usr/lib/ld.so"))
+        );
+        assert_eq!(index.canonical(Path::new("loop")), None);
+    }
+
+    #[test]
+    fn test_relative() {
+        assert_eq!(
+            relative(Path::new("etc/alternatives"), Path::new("lib/ld.so")),
+            PathBuf::from("../../lib/ld.so")
+        );
+        assert_eq!(
+            relative(Path::new(""), Path::new("usr/lib")),
+            PathBuf::from("usr/lib")
+        );
+        assert_eq!(
+            relative(Path::new("usr/lib"), Path::new("usr/lib")),
+            PathBuf::from(".")
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tar::{Archive, EntryType};
+
+use super::checksum::{self, Algorithm};
+use super::special::{self, SpecialFiles};
+use super::symlink::{Index, Symlinks};
+use super::{archive_base, normalize, Options, Unpacker};
+
+/// Diff lists the paths of the download path that differ from the content
+/// of the image, relative to the download path.
+#[derive(Debug, Default)]
+pub struct Diff {
+    // Files found locally that are not in the image
+    pub added: Vec<PathBuf>,
+    // Files of the image that are missing locally
+    pub removed: Vec<PathBuf>,
+    // Files whose content, link target or type differs from the image
+    pub modified: Vec<PathBuf>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.added.len() + self.removed.len() + self.modified.len()
+    }
+
+    /// Logs every difference, one per line
+    pub fn log(&self) {
+        for path in &self.added {
+            warn!("⚠️   added:    {}", path.display());
+        }
+        for path in &self.removed {
+            warn!("⚠️   removed:  {}", path.display());
+        }
+        for path in &self.modified {
+            warn!("⚠️   modified: {}", path.display());
+        }
+    }
+}
+
+// Expected is what a path of the download path should be, according to the
+// image content.
+#[derive(Debug, PartialEq, Eq)]
+enum Expected {
+    File(String),
+    Link(PathBuf),
+    // Device node or FIFO
+    Special,
+}
+
+/// Compares the download path with the tar archive copied out of a container.
+/// Archive paths and symlinks are written the same way as when extracting
+/// with opts, and special files are only expected when they are created.
+/// Directories are not compared.
+///
+/// # Arguments
+///
+/// * `bytes` - The tar archive copied out of the container
+/// * `content_path` - Where in the container filesystem the content was copied from
+/// * `download_path` - Where the archive was previously unpacked
+/// * `opts` - Options the content was extracted with
+pub fn verify(
+    bytes: &[u8],
+    content_path: &Path,
+    download_path: &Path,
+    opts: &Options,
+) -> Result<Diff> {
+    let expected = expected(bytes, &archive_base(content_path), download_path, opts)?;
+    let mut local = BTreeMap::new();
+    walk(download_path, Path::new(""), &mut local)?;
+
+    let mut diff = Diff::default();
+    for (path, expected) in expected {
+        match local.remove(&path) {
+            None => diff.removed.push(path),
+            Some(found) if found != expected => diff.modified.push(path),
+            Some(_) => {}
+        }
+    }
+    diff.added = local.into_keys().collect();
+
+    Ok(diff)
+}
+
+// expected lists the files and links the archive would be unpacked to
+fn expected(
+    bytes: &[u8],
+    base: &Path,
+    root: &Path,
+    opts: &Options,
+) -> Result<BTreeMap<PathBuf, Expected>> {
+    let unpacker = Unpacker::new(root, opts);
+    let index = match opts.symlinks {
+        Symlinks::RewriteRelative | Symlinks::Follow => Some(Index::build(bytes, base)?),
+        Symlinks::Preserve | Symlinks::Skip => None,
+    };
+    let mut expected = BTreeMap::new();
+
+    let mut archive = Archive::new(bytes);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let kind = entry.header().entry_type();
+        let path = entry.path()?.into_owned();
+        let relative = match unpacker.rewrite(&path, kind == EntryType::Directory)? {
+            Some(relative) => relative,
+            None => continue,
+        };
+
+        let file = match kind {
+            EntryType::Regular | EntryType::Continuous => {
+                Expected::File(checksum::sha256(&mut entry)?)
+            }
+            EntryType::Symlink => {
+                let link = match entry.link_name()? {
+                    Some(link) => link.into_owned(),
+                    None => continue,
+                };
+                let path: PathBuf = normalize(&path)?.iter().collect();
+                match (opts.symlinks, &index) {
+                    (Symlinks::Skip, _) => continue,
+                    (Symlinks::RewriteRelative, Some(index)) => {
+                        match unpacker.rewrite_link(index, &path, &link)? {
+                            Some(link) => Expected::Link(link),
+                            None => continue,
+                        }
+                    }
+                    (Symlinks::Follow, Some(index)) => {
+                        match index.canonical(&path) {
+                            Some(target) if !path.starts_with(&target) => {
+                                index.expand(&target, &relative, &mut |entry, dest| {
+                                    if entry.kind != EntryType::Directory {
+                                        let start = entry.position as usize;
+                                        let content = &bytes[start..start + entry.size as usize];
+                                        let digest = checksum::sha256(content)?;
+                                        expected.insert(dest.to_path_buf(), Expected::File(digest));
+                                    }
+                                    Ok(())
+                                })?
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+                    _ => Expected::Link(link),
+                }
+            }
+            // Hard links have the content of the file they point to
+            EntryType::Link => {
+                let target = match entry.link_name()? {
+                    Some(target) => unpacker.rewrite(&target, false)?,
+                    None => None,
+                };
+                match target.and_then(|target| expected.get(&target)) {
+                    Some(Expected::File(digest)) => Expected::File(digest.clone()),
+                    _ => continue,
+                }
+            }
+            kind if special::describe(kind).is_some()
+                && opts.special_files == SpecialFiles::Create =>
+            {
+                Expected::Special
+            }
+            _ => continue,
+        };
+        expected.insert(relative, file);
+    }
+
+    Ok(expected)
+}
+
+// walk lists the files and links found under root.join(dir), without
+// following links. The checksum manifests written by dcp are left out.
+fn walk(root: &Path, dir: &Path, found: &mut BTreeMap<PathBuf, Expected>) -> Result<()> {
+    let entries = match fs::read_dir(root.join(dir)) {
+        Ok(entries) => entries,
+        // A missing download path has every file of the image removed
+        Err(_) if dir.as_os_str().is_empty() => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let relative = dir.join(entry.file_name());
+        if relative == Path::new(Algorithm::Sha256.manifest()) {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(root, &relative, found)?;
+        } else if file_type.is_symlink() {
+            found.insert(relative, Expected::Link(fs::read_link(entry.path())?));
+        } else if file_type.is_file() {
+            let digest = checksum::sha256(fs::File::open(entry.path())?)?;
+            found.insert(relative, Expected::File(digest));
+        } else {
+            found.insert(relative, Expected::Special);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify;
+    use crate::extract::special::SpecialFiles;
+    use crate::extract::symlink::Symlinks;
+    use crate::extract::{extract, Options};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_verify() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        for path in ["manifests/a.yaml", "manifests/b.yaml", "manifests/c.yaml"] {
+            builder
+                .append_data(&mut header.clone(), path, &b"data"[..])
+                .unwrap();
+        }
+        let bytes = builder.into_inner().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("dcp-verify-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.yaml"), "data").unwrap();
+        std::fs::write(dir.join("b.yaml"), "edited").unwrap();
+        std::fs::write(dir.join("sub/d.yaml"), "data").unwrap();
+
+        let opts = Options {
+            strip_components: 1,
+            ..Default::default()
+        };
+        let diff = verify(&bytes, Path::new("/manifests"), &dir, &opts).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(diff.added, vec![PathBuf::from("sub/d.yaml")]);
+        assert_eq!(diff.removed, vec![PathBuf::from("c.yaml")]);
+        assert_eq!(diff.modified, vec![PathBuf::from("b.yaml")]);
+        assert_eq!(diff.len(), 3);
+    }
+
+    #[test]
+    fn test_verify_links() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "manifests/a.yaml", &b"data"[..])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "manifests/latest.yaml", "/manifests/a.yaml")
+            .unwrap();
+        let bytes = builder.into_inner().unwrap();
+
+        let content_path = Path::new("/manifests");
+        let dir = std::env::temp_dir().join(format!("dcp-verify-links-{}", std::process::id()));
+        let mut diffs = Vec::new();
+        for symlinks in [Symlinks::RewriteRelative, Symlinks::Follow, Symlinks::Skip] {
+            let opts = Options {
+                symlinks,
+                ..Default::default()
+            };
+            extract(&bytes, content_path, &dir, &opts).unwrap();
+            let diff = verify(&bytes, content_path, &dir, &opts).unwrap();
+            // The link as found in the image is not the one written
+            let preserved = verify(&bytes, content_path, &dir, &Options::default()).unwrap();
+            std::fs::remove_dir_all(&dir).unwrap();
+            diffs.push((symlinks, diff.len(), preserved.is_empty()));
+        }
+
+        assert_eq!(
+            diffs,
+            vec![
+                (Symlinks::RewriteRelative, 0, false),
+                (Symlinks::Follow, 0, false),
+                (Symlinks::Skip, 0, false)
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_verify_special_files() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Fifo);
+        header.set_size(0);
+        header.set_mode(0o600);
+        builder
+            .append_data(&mut header, "dev/fifo", &b""[..])
+            .unwrap();
+        let bytes = builder.into_inner().unwrap();
+
+        let content_path = Path::new("/dev");
+        let dir = std::env::temp_dir().join(format!("dcp-verify-fifo-{}", std::process::id()));
+        let create = Options {
+            special_files: SpecialFiles::Create,
+            ..Default::default()
+        };
+        extract(&bytes, content_path, &dir, &create).unwrap();
+        let created = verify(&bytes, content_path, &dir, &create).unwrap();
+        let skipped = verify(&bytes, content_path, &dir, &Options::default()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(created.is_empty());
+        assert_eq!(skipped.added, vec![PathBuf::from("dev/fifo")]);
+    }
+}
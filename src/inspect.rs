@@ -0,0 +1,123 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Write;
+
+/// Metadata is a normalized view of the configuration and manifest of an
+/// image, described the same way whatever the runtime it was read from.
+#[derive(Debug, Default, Serialize)]
+pub struct Metadata {
+    // Image reference as provided by the user
+    pub image: String,
+    // Id of the image on the runtime
+    pub id: String,
+    // Repository digest of the image, if it has one
+    pub digest: Option<String>,
+    pub repo_tags: Vec<String>,
+    // Creation date, in RFC 3339 format
+    pub created: Option<String>,
+    pub author: Option<String>,
+    pub architecture: Option<String>,
+    pub os: Option<String>,
+    pub user: Option<String>,
+    pub working_dir: Option<String>,
+    pub entrypoint: Vec<String>,
+    pub cmd: Vec<String>,
+    pub env: Vec<String>,
+    pub labels: BTreeMap<String, String>,
+    // Digests of the uncompressed layers, base layer first
+    pub layers: Vec<String>,
+    // Total size of the image in bytes
+    pub size: u64,
+}
+
+impl Metadata {
+    /// Writes the metadata to stdout, as JSON or as human-readable text
+    pub fn write(&self, json: bool) -> Result<()> {
+        let output = if json {
+            let mut output = serde_json::to_string_pretty(self)?;
+            output.push('\n');
+            output
+        } else {
+            self.text()
+        };
+        std::io::stdout().write_all(output.as_bytes())?;
+        Ok(())
+    }
+
+    // text describes the metadata one field per line, lists indented below
+    // their name
+    fn text(&self) -> String {
+        let mut text = String::new();
+        let mut field = |name: &str, value: &str| {
+            let _ = writeln!(text, "{:<14}{}", format!("{}:", name), value);
+        };
+
+        field("Image", &self.image);
+        field("Id", &self.id);
+        field("Digest", self.digest.as_deref().unwrap_or("-"));
+        field("Tags", &self.repo_tags.join(", "));
+        field("Created", self.created.as_deref().unwrap_or("-"));
+        field("Author", self.author.as_deref().unwrap_or("-"));
+        let platform = match (&self.os, &self.architecture) {
+            (Some(os), Some(architecture)) => format!("{}/{}", os, architecture),
+            (Some(platform), None) | (None, Some(platform)) => platform.clone(),
+            (None, None) => "-".to_string(),
+        };
+        field("Platform", &platform);
+        field("Size", &format!("{} bytes", self.size));
+        field("User", self.user.as_deref().unwrap_or("-"));
+        field("Working dir", self.working_dir.as_deref().unwrap_or("-"));
+        field("Entrypoint", &self.entrypoint.join(" "));
+        field("Cmd", &self.cmd.join(" "));
+
+        let mut list = |name: &str, items: Vec<String>| {
+            let _ = writeln!(text, "{} ({}):", name, items.len());
+            for item in items {
+                let _ = writeln!(text, "  {}", item);
+            }
+        };
+        list("Env", self.env.clone());
+        list(
+            "Labels",
+            self.labels
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect(),
+        );
+        list("Layers", self.layers.clone());
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metadata;
+
+    #[test]
+    fn test_text() {
+        let metadata = Metadata {
+            image: "quay.io/tflannag/bundles:resolveset-v0.0.2".to_string(),
+            id: "sha256:1234".to_string(),
+            os: Some("linux".to_string()),
+            architecture: Some("amd64".to_string()),
+            size: 42,
+            labels: [("operators.operatorframework.io.bundle.package.v1", "etcd")]
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            layers: vec!["sha256:abcd".to_string()],
+            ..Default::default()
+        };
+
+        let text = metadata.text();
+        assert!(text.contains("Platform:     linux/amd64\n"));
+        assert!(text.contains("Size:         42 bytes\n"));
+        assert!(text.contains(
+            "Env (0):\nLabels (1):\n  operators.operatorframework.io.bundle.package.v1=etcd\n"
+        ));
+        assert!(text.ends_with("Layers (1):\n  sha256:abcd\n"));
+    }
+}
@@ -1,7 +1,33 @@
 use anyhow::{anyhow, Result};
+use futures_util::{stream, StreamExt};
+use std::path::Path;
+use std::time::Instant;
 
+use reference::Reference;
+use report::Report;
+use runtime::container::Container;
+use runtime::Connection;
+
+pub use error::Error;
+
+pub mod archive;
+pub mod batch;
 pub mod config;
+pub mod diff;
+pub mod error;
+pub mod extract;
+pub mod inspect;
+pub mod logging;
+pub mod policy;
+pub mod progress;
+pub mod pull;
+pub mod reference;
+pub mod report;
+pub mod retry;
 mod runtime;
+pub mod settings;
+pub mod signature;
+pub mod template;
 
 extern crate pretty_env_logger;
 #[macro_use]
@@ -13,47 +39,778 @@ extern crate log;
 /// 2. Create a container, receiving the container id as a response
 /// 3. Copy the container content to the specified directory
 /// 4. Delete the container
-pub async fn run(cfg: config::Config) -> Result<()> {
-    pretty_env_logger::formatted_builder()
-        .parse_filters(&cfg.log_level.clone())
-        .init();
+///
+/// On a dry run, nothing is pulled or written: dcp only reports what would happen.
+/// When a report is requested, it is written at the end of the run, even if the run failed.
+///
+/// The verify command pulls the image the same way, but only compares its content with the
+/// download path and fails if they differ. The inspect command prints the metadata of the image,
+/// and the diff command compares its content with the content of another image. The batch
+/// command copies the content of every image of a batch file, a few at a time, over a single
+/// runtime connection. Several images provided on the command line are copied the same way.
+///
+/// Failures are returned as an [`Error`] telling apart their kind, such as a missing image or
+/// refused credentials, each with its own exit code for the CLI.
+pub async fn run(cfg: config::Config) -> std::result::Result<(), Error> {
+    logging::init(&cfg.log_level);
+    progress::init(cfg.quiet);
 
-    // Build the runtime
-    let rt = if let Some(runtime) = runtime::set(&cfg.socket).await {
-        runtime
+    // The operations of the run give up once its timeout is over, the run
+    // itself goes on to remove its containers and write its report
+    let result = match cfg.timeout {
+        Some(timeout) => retry::deadline(timeout, dispatch(cfg)).await,
+        None => dispatch(cfg).await,
+    };
+    result.map_err(Error::from)
+}
+
+// dispatch runs the command of the configuration.
+async fn dispatch(cfg: config::Config) -> Result<()> {
+    match cfg.command {
+        config::Command::Verify => return verify(cfg).await,
+        config::Command::Inspect => return inspect(cfg).await,
+        config::Command::Diff => return diff(cfg).await,
+        config::Command::Batch => return batch(cfg).await,
+        config::Command::Copy => {}
+    }
+    if cfg.images.len() > 1 {
+        return copy_images(cfg).await;
+    }
+
+    let format = cfg.report;
+    let report_file = cfg.report_file.clone();
+    let connection = Connection::new(&cfg.socket, cfg.connect_timeout);
+    let (report, result) = copy(cfg, &connection).await;
+
+    // Write the report, even if the run failed
+    if let Some(format) = format {
+        match report.write(format, report_file.as_deref()) {
+            Ok(_) => {}
+            Err(e) if result.is_ok() => {
+                return Err(anyhow!("❌ error writing the report: {}", e));
+            }
+            Err(e) => error!("❌ error writing the report: {}", e),
+        }
+    }
+
+    result
+}
+
+// copy_images copies the content of every image provided, a few at a time,
+// and fails if any of them failed. Lines logged about an image are tagged
+// with its reference.
+async fn copy_images(cfg: config::Config) -> Result<()> {
+    if !cfg.write_to_stdout && !template::is_template(&cfg.download_path) {
+        warn!(
+            "⚠️ {} images are copied to {}, use placeholders such as {{name}} to copy them apart",
+            cfg.images.len(),
+            cfg.download_path
+        );
+    }
+
+    let connection = Connection::new(&cfg.socket, cfg.connect_timeout);
+    let runs: Vec<(Report, Result<()>)> = stream::iter(&cfg.images)
+        .map(|image| {
+            let mut cfg = cfg.clone();
+            cfg.image = image.clone();
+            logging::tagged(image.clone(), copy(cfg, &connection))
+        })
+        .buffered(cfg.jobs)
+        .collect()
+        .await;
+
+    let mut written = Ok(());
+    if let Some(format) = cfg.report {
+        let reports: Vec<&Report> = runs.iter().map(|(report, _)| report).collect();
+        written = Report::write_all(&reports, format, cfg.report_file.as_deref());
+    }
+
+    // The runs are in the order of the images
+    let results: Vec<(&str, Result<()>)> = cfg
+        .images
+        .iter()
+        .map(String::as_str)
+        .zip(runs.into_iter().map(|(_, result)| result))
+        .collect();
+    let result = summarize("Copy", &results);
+    match written {
+        Ok(_) => result,
+        Err(e) if result.is_ok() => Err(anyhow!("❌ error writing the report: {}", e)),
+        Err(e) => {
+            error!("❌ error writing the report: {}", e);
+            result
+        }
+    }
+}
+
+// copy copies the content of the image, and returns the report of the run
+// along with its result.
+async fn copy(cfg: config::Config, connection: &Connection) -> (Report, Result<()>) {
+    let mut report = Report {
+        image: cfg.image.clone(),
+        content_path: cfg.content_path.clone(),
+        download_path: cfg.download_path.clone(),
+        output: cfg
+            .extract
+            .output
+            .as_ref()
+            .map(|output| output.path.clone()),
+        dry_run: cfg.extract.dry_run,
+        ..Default::default()
+    };
+
+    let started = Instant::now();
+    let result = execute(cfg, connection, &mut report).await;
+
+    report.timings.total_ms = started.elapsed().as_millis();
+    report.success = result.is_ok();
+    if let Err(e) = &result {
+        report.error = Some(e.to_string().trim_start_matches("❌ ").to_string());
+    }
+    (report, result)
+}
+
+// execute pulls the image and copies its content, recording what happened
+// in the report along the way.
+async fn execute(
+    mut cfg: config::Config,
+    connection: &Connection,
+    report: &mut Report,
+) -> Result<()> {
+    // Evaluate the trust policy before anything is pulled
+    let scope = load_policy(&cfg, &cfg.image)?;
+    if let Some(scope) = &scope {
+        report.policy = Some(scope.decision.clone());
+        if let Err(e) = scope.admit() {
+            return Err(
+                Error::Rejected(format!("❌ the trust policy rejected the image: {}", e)).into(),
+            );
+        }
+    }
+
+    let reference = apply_registry(&mut cfg)?;
+    let (container, runtime) = connect(reference.clone(), connection).await?;
+    report.runtime = Some(runtime.to_string());
+
+    // Pull the image, or only report whether it would be pulled on a dry run
+    let started = Instant::now();
+    if cfg.extract.dry_run {
+        let present = container.present_locally().await;
+        if needs_pull(container.as_ref(), &cfg, &reference, present).await? {
+            info!("🔧 Dry run: the image would be pulled");
+        } else {
+            info!("🔧 Dry run: the image is present locally and would not be pulled");
+        }
+        if !present {
+            info!("🔧 Dry run: the files to write cannot be listed until the image is pulled");
+            return Ok(());
+        }
     } else {
-        return Err(anyhow!("❌ no valid container runtime"));
+        report.pulled = retry::within(pull(container.as_ref(), &cfg, &reference)).await?;
+    }
+    report.timings.pull_ms = started.elapsed().as_millis();
+
+    let rm_image = cfg.rm_image;
+    let result = copy_content(container.as_ref(), cfg, scope, report).await;
+
+    // Only remove the images pulled by this run, never the ones that were
+    // already present locally
+    if rm_image && report.pulled {
+        return removed(result, container.remove().await);
+    }
+    result
+}
+
+// removed logs the removal of the image pulled by the run. Failing to remove
+// it fails the run, unless the copy already failed: that error is kept.
+fn removed(result: Result<()>, removal: Result<()>) -> Result<()> {
+    match removal {
+        Ok(()) => info!("📦 Removed the image pulled by this run"),
+        Err(e) if result.is_ok() => {
+            return Err(anyhow!("❌ error removing the image: {}", e));
+        }
+        Err(e) => warn!("⚠️ cannot remove the image pulled by this run: {}", e),
+    }
+    result
+}
+
+// copy_content checks the pulled image against the expected digest, the
+// trust policy and its signature, then copies its content.
+async fn copy_content(
+    container: &dyn Container,
+    mut cfg: config::Config,
+    mut scope: Option<policy::Scope>,
+    report: &mut Report,
+) -> Result<()> {
+    report.digest = retry::within(resolve_digest(container, cfg.expect_digest.as_deref())).await?;
+    if let Some(scope) = &mut scope {
+        retry::within(accept(scope, report.digest.as_deref(), &cfg)).await?;
+        report.policy = Some(scope.decision.clone());
+    }
+
+    // Expand the placeholders of the destination now that the digest is known
+    if let Err(e) = expand_destination(&mut cfg, report) {
+        return Err(anyhow!("❌ error expanding the destination: {}", e));
+    }
+
+    // Refuse to copy anything out of an image that is not signed with the key
+    if let Some(opts) = &cfg.signature {
+        let digest = match &report.digest {
+            Some(digest) => digest,
+            None => {
+                return Err(Error::Rejected(
+                    "❌ the image has no repository digest, its signature cannot be verified"
+                        .to_string(),
+                )
+                .into())
+            }
+        };
+        // The mirror only serves the signature, which must be made for the
+        // image the user asked for
+        let verified = signature::verify(digest, &cfg.image, opts, &cfg.username, &cfg.password);
+        if let Err(e) = retry::within(verified).await {
+            if matches!(Error::classify(&e), Error::Timeout(_)) {
+                return Err(e);
+            }
+            return Err(Error::Rejected(format!(
+                "❌ error verifying the signature of the image: {}",
+                e
+            ))
+            .into());
+        }
+        info!(
+            "✅ Verified the signature of the image with {}",
+            opts.key.display()
+        );
+    }
+
+    // Copy files from the image
+    let started = Instant::now();
+    // Only reading the content out of the runtime is retried, an attempt
+    // must not unpack over the partial output of a failed one
+    let copied = match &cfg.layers {
+        Some(selection) => copy_layers(container, selection, &cfg).await,
+        None => {
+            container
+                .copy_files(
+                    cfg.content_path.clone(),
+                    cfg.download_path.clone(),
+                    cfg.write_to_stdout,
+                    &cfg.extract,
+                    cfg.retries,
+                )
+                .await
+        }
+    };
+    let summary = match copied {
+        Ok(summary) => summary,
+        // Keep limit breaches distinguishable, they have their own exit code
+        Err(e) if e.is::<extract::limits::LimitExceeded>() => return Err(e),
+        Err(e) => {
+            let message = format!("❌ error copying the image's files: {}", e);
+            return Err(error::copy_failed(e, message));
+        }
     };
 
-    // Build the image struct
-    let container = match runtime::container::new(cfg.image, rt) {
-        Ok(i) => i,
+    summary.log();
+
+    if cfg.report.is_some() {
+        if let Err(e) = report.record(summary, started.elapsed()) {
+            return Err(anyhow!("❌ error reading the written files: {}", e));
+        }
+    }
+
+    Ok(())
+}
+
+// batch copies the content of every image of the batch file, a few at a
+// time, and fails if any of them failed.
+async fn batch(cfg: config::Config) -> Result<()> {
+    let path = cfg.batch_file.clone().unwrap_or_default();
+    let batch = match batch::Batch::read(&path) {
+        Ok(batch) => batch,
         Err(e) => {
-            return Err(anyhow!("❌ error building the image: {}", e));
+            return Err(Error::Usage(format!(
+                "❌ error reading the batch file {}: {}",
+                path.display(),
+                e
+            ))
+            .into())
         }
     };
 
-    // Pull the image
-    match container
-        .pull(cfg.username, cfg.password, cfg.force_pull)
+    let connection = Connection::new(&cfg.socket, cfg.connect_timeout);
+    let results: Vec<(&str, Result<()>)> = stream::iter(&batch.jobs)
+        .map(|job| async {
+            let result = logging::tagged(job.image.clone(), run_job(job, &cfg, &connection));
+            (job.image.as_str(), result.await)
+        })
+        .buffer_unordered(cfg.jobs)
+        .collect()
+        .await;
+
+    summarize("Batch", &results)
+}
+
+// summarize logs which images succeeded and fails if any of them failed
+fn summarize(name: &str, results: &[(&str, Result<()>)]) -> Result<()> {
+    let failed: Vec<_> = results
+        .iter()
+        .filter_map(|(image, result)| result.as_ref().err().map(|e| (image, e)))
+        .collect();
+    info!(
+        "📦 {} done: {} succeeded, {} failed",
+        name,
+        results.len() - failed.len(),
+        failed.len()
+    );
+    for (image, e) in &failed {
+        error!(
+            "❌   {}: {}",
+            image,
+            e.to_string().trim_start_matches("❌ ")
+        );
+    }
+
+    if !failed.is_empty() {
+        let message = format!(
+            "❌ {} of {} images of the {} failed",
+            failed.len(),
+            results.len(),
+            name.to_lowercase()
+        );
+        let errors = failed.iter().map(|(_, e)| *e);
+        return Err(Error::summarize(errors, message).into());
+    }
+    Ok(())
+}
+
+// run_job copies every content path of a job, stopping at the first one
+// that fails. Only the pulls and copies are retried, the job is not.
+async fn run_job(job: &batch::Job, cfg: &config::Config, connection: &Connection) -> Result<()> {
+    let configs = match job.configs(cfg) {
+        Ok(configs) => configs,
+        Err(e) => return Err(Error::Usage(format!("❌ invalid job options: {}", e)).into()),
+    };
+    let rm_image = job.options.rm_image || cfg.rm_image;
+
+    // The image is removed once every content path is copied, if it was
+    // pulled by the job
+    let mut pulled = false;
+    let mut result = Ok(());
+    for cfg in &configs {
+        let mut report = Report::default();
+        result = execute(cfg.clone(), connection, &mut report).await;
+        pulled |= report.pulled;
+        if result.is_err() {
+            break;
+        }
+    }
+
+    if rm_image && pulled {
+        return removed(result, remove_image(&configs[0], connection).await);
+    }
+    result
+}
+
+// remove_image removes the image of the configuration from the runtime.
+async fn remove_image(cfg: &config::Config, connection: &Connection) -> Result<()> {
+    let mut cfg = cfg.clone();
+    let reference = apply_registry(&mut cfg)?;
+    let (container, _) = connect(reference, connection).await?;
+    container.remove().await
+}
+
+// verify compares the download path with the content of the image and fails
+// if they differ.
+async fn verify(cfg: config::Config) -> Result<()> {
+    let connection = Connection::new(&cfg.socket, cfg.connect_timeout);
+    let container = prepare(&cfg, &connection, &cfg.image, cfg.expect_digest.as_deref()).await?;
+
+    let bytes = match container
+        .read_files(cfg.content_path.clone(), &cfg.extract.limits)
         .await
     {
-        Ok(_) => {}
+        Ok(bytes) => bytes,
+        Err(e) => {
+            let message = format!("❌ error copying the image's files: {}", e);
+            return Err(error::copy_failed(e, message));
+        }
+    };
+
+    let download_path = Path::new(&cfg.download_path);
+    let content_path = Path::new(&cfg.content_path);
+    let diff = match extract::verify::verify(&bytes, content_path, download_path, &cfg.extract) {
+        Ok(diff) => diff,
         Err(e) => {
-            return Err(anyhow!("❌ error building the image: {}", e));
+            return Err(anyhow!("❌ error verifying the download path: {}", e));
         }
+    };
+
+    if diff.is_empty() {
+        info!("✅ {} matches the image content", download_path.display());
+        return Ok(());
     }
 
-    // Copy files from the image
-    match container
-        .copy_files(cfg.content_path, cfg.download_path, cfg.write_to_stdout)
-        .await
-    {
-        Ok(_) => {}
+    warn!(
+        "⚠️ {} files of {} differ from the image content:",
+        diff.len(),
+        download_path.display()
+    );
+    diff.log();
+    Err(anyhow!(
+        "❌ {} differs from the image content",
+        download_path.display()
+    ))
+}
+
+// inspect pulls the image and prints its metadata
+async fn inspect(cfg: config::Config) -> Result<()> {
+    let connection = Connection::new(&cfg.socket, cfg.connect_timeout);
+    let container = prepare(&cfg, &connection, &cfg.image, cfg.expect_digest.as_deref()).await?;
+
+    let metadata = match container.inspect().await {
+        Ok(metadata) => metadata,
+        Err(e) => return Err(anyhow!("❌ error inspecting the image: {}", e)),
+    };
+    if let Err(e) = metadata.write(cfg.json) {
+        return Err(anyhow!("❌ error writing the image metadata: {}", e));
+    }
+    Ok(())
+}
+
+// diff compares the content of the image with the content of another image
+// and prints the differences.
+async fn diff(cfg: config::Config) -> Result<()> {
+    let other_image = cfg.other_image.clone().unwrap_or_default();
+    let connection = Connection::new(&cfg.socket, cfg.connect_timeout);
+
+    let mut archives = Vec::new();
+    for image in [&cfg.image, &other_image] {
+        let container = prepare(&cfg, &connection, image, None).await?;
+        match container
+            .read_files(cfg.content_path.clone(), &cfg.extract.limits)
+            .await
+        {
+            Ok(bytes) => archives.push(bytes),
+            Err(e) => {
+                let message = format!("❌ error copying the files of {}: {}", image, e);
+                return Err(error::copy_failed(e, message));
+            }
+        }
+    }
+
+    let diff = match diff::Diff::compare(
+        (&cfg.image, &archives[0]),
+        (&other_image, &archives[1]),
+        cfg.unified,
+    ) {
+        Ok(diff) => diff,
+        Err(e) => return Err(anyhow!("❌ error comparing the images: {}", e)),
+    };
+    if diff.is_empty() {
+        info!("✅ {} and {} have the same content", cfg.image, other_image);
+    }
+    if let Err(e) = diff.write(cfg.json) {
+        return Err(anyhow!("❌ error writing the differences: {}", e));
+    }
+    Ok(())
+}
+
+// copy_layers extracts what the selected layers of the image add under the
+// content path, instead of the merged filesystem of a container.
+async fn copy_layers(
+    container: &dyn Container,
+    selection: &extract::layer::Selection,
+    cfg: &config::Config,
+) -> Result<extract::Summary> {
+    let image = retry::within(retry::retry(cfg.retries, "exporting the image", || {
+        container.export(&cfg.extract.limits)
+    }))
+    .await?;
+    let layers = extract::layer::read(&image, selection, &cfg.extract.limits)?;
+    for layer in &layers {
+        info!("📦 Extracting layer {} {}", layer.index, layer.digest);
+    }
+
+    let content_path = Path::new(&cfg.content_path);
+    let content = extract::layer::combine(&layers, content_path)?;
+    let mut summary = extract::extract(
+        &content.bytes,
+        content_path,
+        Path::new(&cfg.download_path),
+        &cfg.extract,
+    )?;
+    summary.whiteouts = content.whiteouts;
+    Ok(summary)
+}
+
+// expand_destination replaces the placeholders of the download path and of
+// the output archive with the parts of the image reference and its digest.
+fn expand_destination(cfg: &mut config::Config, report: &mut Report) -> Result<()> {
+    let digest = report.digest.as_deref();
+    cfg.download_path = template::expand(&cfg.download_path, &cfg.image, digest)?;
+    report.download_path = cfg.download_path.clone();
+    if let Some(output) = &mut cfg.extract.output {
+        let path = template::expand(&output.path.to_string_lossy(), &cfg.image, digest)?;
+        output.path = path.into();
+        report.output = Some(output.path.clone());
+    }
+    Ok(())
+}
+
+// prepare makes sure the image is present locally and trusted, for the
+// commands that only read it.
+async fn prepare(
+    cfg: &config::Config,
+    connection: &Connection,
+    image: &str,
+    expect_digest: Option<&str>,
+) -> Result<Box<dyn Container>> {
+    let mut scope = load_policy(cfg, image)?;
+    if let Some(scope) = &scope {
+        if let Err(e) = scope.admit() {
+            return Err(
+                Error::Rejected(format!("❌ the trust policy rejected the image: {}", e)).into(),
+            );
+        }
+    }
+
+    let cfg = &mut config::Config {
+        image: image.to_string(),
+        ..cfg.clone()
+    };
+    let reference = apply_registry(cfg)?;
+    let (container, _) = connect(reference.clone(), connection).await?;
+
+    retry::within(pull(container.as_ref(), cfg, &reference)).await?;
+    let digest = retry::within(resolve_digest(container.as_ref(), expect_digest)).await?;
+    if let Some(scope) = &mut scope {
+        retry::within(accept(scope, digest.as_deref(), cfg)).await?;
+    }
+
+    Ok(container)
+}
+
+// pull makes sure the image is present locally, pulling it when the pull
+// policy requires it. Returns whether the image was pulled.
+async fn pull(container: &dyn Container, cfg: &config::Config, reference: &str) -> Result<bool> {
+    let present = container.present_locally().await;
+    if !needs_pull(container, cfg, reference, present).await? {
+        debug!("✅ Skipping the pull process as the image was found locally");
+        return Ok(false);
+    }
+
+    let pulled = retry::retry(cfg.retries, "pulling the image", || {
+        container.pull(
+            cfg.username.clone(),
+            cfg.password.clone(),
+            true,
+            cfg.insecure,
+        )
+    })
+    .await;
+    match pulled {
+        Ok(pulled) => Ok(pulled),
         Err(e) => {
-            return Err(anyhow!("❌ error copying the image's files: {}", e));
+            let message = format!("❌ error building the image: {}", e);
+            Err(error::pull_failed(e, message))
         }
     }
+}
+
+// needs_pull returns whether the pull policy requires pulling the image, and
+// fails if it is missing but may not be pulled.
+async fn needs_pull(
+    container: &dyn Container,
+    cfg: &config::Config,
+    reference: &str,
+    present: bool,
+) -> Result<bool> {
+    match cfg.pull {
+        pull::Policy::Always => Ok(true),
+        pull::Policy::Missing => Ok(!present),
+        pull::Policy::Never if !present => Err(Error::ImageNotFound(format!(
+            "❌ {} is not present locally and the pull policy is never",
+            reference
+        ))
+        .into()),
+        pull::Policy::Never => Ok(false),
+        pull::Policy::Newer if !present => Ok(true),
+        pull::Policy::Newer => Ok(changed(container, cfg, reference).await),
+    }
+}
 
+// changed returns whether the registry serves another image for the tag
+// than the one present locally. The local image is kept when the registry
+// cannot be reached.
+async fn changed(container: &dyn Container, cfg: &config::Config, reference: &str) -> bool {
+    let parsed = Reference::parse(reference);
+    if parsed.digest.is_some() {
+        debug!("🔧 The image is referenced by digest, it cannot change");
+        return false;
+    }
+    let (repo, tag) = (&parsed.repo, parsed.tag_or_latest());
+    let remote = match pull::remote_digest(repo, tag, &cfg.username, &cfg.password).await {
+        Ok(digest) => digest,
+        Err(e) => {
+            warn!(
+                "⚠️ Could not read the digest of {} from its registry, using the local image: {}",
+                reference, e
+            );
+            return false;
+        }
+    };
+
+    let local = container.digest().await.ok().flatten();
+    match local.as_deref().and_then(|digest| digest.split_once('@')) {
+        Some((_, local)) if local == remote => {
+            debug!("✅ The local image is the latest {}", reference);
+            false
+        }
+        _ => {
+            info!("🔧 The registry has a newer image for {}", reference);
+            true
+        }
+    }
+}
+
+// apply_registry applies the settings of the registry of the image read
+// from the config files: its credentials, unless some were provided, and
+// whether it is insecure. Returns the reference to pull the image with,
+// from the mirror of the registry if it has one. The trust policy and the
+// signatures still apply to the image as provided.
+fn apply_registry(cfg: &mut config::Config) -> Result<String> {
+    let (registry, reference) = match settings::find(&cfg.registries, &cfg.image) {
+        Some(found) => found,
+        None => return Ok(cfg.image.clone()),
+    };
+
+    if cfg.username.is_empty() {
+        match registry.credentials() {
+            Ok(Some((username, password))) => {
+                cfg.username = username;
+                cfg.password = password;
+            }
+            Ok(None) => {}
+            Err(e) => return Err(anyhow!("❌ error reading the registry credentials: {}", e)),
+        }
+    }
+    cfg.insecure = registry.insecure;
+    if reference != cfg.image {
+        info!("🔧 Pulling {} from the mirror {}", cfg.image, reference);
+    }
+    Ok(reference)
+}
+
+// load_policy loads the trust policy and returns the scope that applies to
+// the image, unless policies are disabled or none exists.
+fn load_policy(cfg: &config::Config, image: &str) -> Result<Option<policy::Scope>> {
+    if cfg.insecure_policy {
+        debug!("🔧 Skipping the trust policy");
+        return Ok(None);
+    }
+
+    let (path, policy) = match policy::Policy::find(cfg.policy.as_deref()) {
+        Ok(Some(found)) => found,
+        Ok(None) => {
+            debug!("🔧 No trust policy found, accepting any image");
+            return Ok(None);
+        }
+        Err(e) => return Err(anyhow!("❌ error loading the trust policy: {}", e)),
+    };
+
+    let scope = policy.scope(&path, image);
+    info!(
+        "🔧 Trust policy {} requires {} for {}",
+        path.display(),
+        scope.decision.requirements.join(", "),
+        scope.decision.scope
+    );
+    Ok(Some(scope))
+}
+
+// accept verifies the signatures the trust policy requires once the image
+// is present locally.
+async fn accept(
+    scope: &mut policy::Scope,
+    digest: Option<&str>,
+    cfg: &config::Config,
+) -> Result<()> {
+    if let Err(e) = scope.verify(digest, &cfg.username, &cfg.password).await {
+        return Err(
+            Error::Rejected(format!("❌ the trust policy rejected the image: {}", e)).into(),
+        );
+    }
+    info!("✅ The trust policy accepted the image");
     Ok(())
 }
+
+// resolve_digest logs the repository digest of the image present locally and
+// fails if it is not the expected one.
+async fn resolve_digest(
+    container: &dyn Container,
+    expected: Option<&str>,
+) -> Result<Option<String>> {
+    let digest = match container.digest().await {
+        Ok(digest) => digest,
+        Err(e) if expected.is_some() => {
+            return Err(anyhow!("❌ error reading the digest of the image: {}", e));
+        }
+        Err(e) => {
+            debug!("🔧 Could not read the digest of the image: {}", e);
+            None
+        }
+    };
+
+    match &digest {
+        Some(digest) => info!("📦 Using image {}", digest),
+        None => debug!("📦 The image has no repository digest"),
+    }
+
+    if let Some(expected) = expected {
+        match digest.as_deref().and_then(|digest| digest.split_once('@')) {
+            Some((_, actual)) if actual == expected => {
+                debug!("✅ The image digest matches {}", expected)
+            }
+            Some((_, actual)) => {
+                return Err(Error::Rejected(format!(
+                    "❌ the image digest {} does not match the expected digest {}",
+                    actual, expected
+                ))
+                .into());
+            }
+            None => {
+                return Err(Error::Rejected(format!(
+                    "❌ the image has no repository digest to compare with {}",
+                    expected
+                ))
+                .into());
+            }
+        }
+    }
+
+    Ok(digest)
+}
+
+// connect builds the container for image on the runtime of the connection,
+// and returns it along with the name of the runtime.
+async fn connect(
+    image: String,
+    connection: &Connection,
+) -> Result<(Box<dyn Container>, &'static str)> {
+    // Build the runtime
+    let rt = if let Some(runtime) = connection.runtime().await {
+        runtime.clone()
+    } else {
+        return Err(Error::NoRuntime("❌ no valid container runtime".to_string()).into());
+    };
+    let name = rt.name();
+    debug!("🔧 Using the {} runtime", name);
+
+    // Build the image struct
+    match runtime::container::new(image, rt) {
+        Ok(container) => Ok((container, name)),
+        Err(e) => Err(anyhow!("❌ error building the image: {}", e)),
+    }
+}
@@ -0,0 +1,79 @@
+use log::{Log, Metadata, Record};
+use std::future::Future;
+
+use crate::progress;
+
+tokio::task_local! {
+    // Tag of the image the lines logged by the current task are about
+    static TAG: String;
+}
+
+// Tagged prefixes the lines logged while processing an image with the tag
+// of that image, so that lines of images processed at the same time can be
+// told apart.
+struct Tagged<L> {
+    inner: L,
+}
+
+impl<L: Log> Log for Tagged<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        // Keep the progress bars from drawing over the line
+        progress::suspend(|| self.write(record))
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+impl<L: Log> Tagged<L> {
+    fn write(&self, record: &Record) {
+        let tagged = TAG.try_with(|tag| {
+            self.inner.log(
+                &Record::builder()
+                    .args(format_args!("[{}] {}", tag, record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            )
+        });
+        if tagged.is_err() {
+            self.inner.log(record);
+        }
+    }
+}
+
+/// Initializes the logger with the provided filters, such as `info`
+pub fn init(filters: &str) {
+    let logger = pretty_env_logger::formatted_builder()
+        .parse_filters(filters)
+        .build();
+    let level = logger.filter();
+    if log::set_boxed_logger(Box::new(Tagged { inner: logger })).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Returns the tag of the image processed by the current task, if any
+pub fn tag() -> Option<String> {
+    TAG.try_with(String::clone).ok()
+}
+
+/// Runs the future with every line it logs prefixed with the tag
+///
+/// # Arguments
+///
+/// * `tag` - Tag of the image the future processes, such as its reference
+/// * `future` - Future processing the image
+pub async fn tagged<F: Future>(tag: String, future: F) -> F::Output {
+    TAG.scope(tag, future).await
+}
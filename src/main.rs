@@ -1,18 +1,26 @@
-use anyhow::Result;
+use dcp::Error;
 
 extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     match dcp::config::get_args() {
         Err(e) => {
-            error!("❌ error reading arguments {}", e);
-            std::process::exit(1)
+            // The logger is only set up by the run, print the error directly
+            let e = Error::Usage(e.to_string().trim_start_matches("❌ ").to_string());
+            eprintln!("❌ error reading arguments: {}", e);
+            std::process::exit(e.exit_code())
+        }
+        Ok(config) => {
+            if let Err(e) = dcp::run(config).await {
+                match e {
+                    Error::LimitExceeded(_) => error!("❌ aborted extraction: {}", e),
+                    _ => error!("❌ {}", e),
+                }
+                std::process::exit(e.exit_code())
+            }
         }
-        Ok(config) => dcp::run(config).await?,
     }
-
-    Ok(())
 }
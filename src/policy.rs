@@ -0,0 +1,417 @@
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::reference::Reference;
+use crate::signature::{self, Identity};
+
+/// System-wide policy file, used when the user has none
+pub const SYSTEM_POLICY: &str = "/etc/containers/policy.json";
+
+// Transport of the images pulled from registries
+const DOCKER_TRANSPORT: &str = "docker";
+
+/// Policy is a containers-policy.json(5) trust policy, as used by podman and
+/// skopeo, deciding which images may be pulled.
+#[derive(Debug, Deserialize)]
+pub struct Policy {
+    default: Vec<Requirement>,
+    #[serde(default)]
+    transports: HashMap<String, HashMap<String, Vec<Requirement>>>,
+}
+
+/// Requirement is a single rule of a policy. An image is only accepted when
+/// every requirement of its scope accepts it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Requirement {
+    InsecureAcceptAnything,
+    Reject,
+    // GPG simple signing, which dcp cannot verify
+    SignedBy {},
+    #[serde(rename_all = "camelCase")]
+    SigstoreSigned {
+        key_path: Option<PathBuf>,
+        key_data: Option<String>,
+        // Identity the signatures must be made for, matchRepoDigestOrExact when absent
+        signed_identity: Option<SignedIdentity>,
+    },
+}
+
+/// SignedIdentity is how the `docker-reference` of a signature must relate to
+/// the image, as the `signedIdentity` of a `sigstoreSigned` requirement.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SignedIdentity {
+    MatchExact,
+    MatchRepoDigestOrExact,
+    MatchRepository,
+    #[serde(rename_all = "camelCase")]
+    ExactReference {
+        docker_reference: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    ExactRepository {
+        docker_repository: String,
+    },
+    // Rewriting of the image prefix, which dcp does not support
+    RemapIdentity {},
+}
+
+impl SignedIdentity {
+    // identity returns the identity the signatures of the image must be made for
+    fn identity(&self, image: &Reference) -> Result<Identity> {
+        let exact = |reference: &Reference| Identity::Exact(reference.qualify().to_string());
+        Ok(match self {
+            SignedIdentity::MatchExact => exact(image),
+            SignedIdentity::MatchRepoDigestOrExact if image.digest.is_some() => {
+                Identity::Repository(image.name())
+            }
+            SignedIdentity::MatchRepoDigestOrExact => exact(image),
+            SignedIdentity::MatchRepository => Identity::Repository(image.name()),
+            SignedIdentity::ExactReference { docker_reference } => {
+                let reference = Reference::parse(docker_reference);
+                if reference.tag.is_none() && reference.digest.is_none() {
+                    return Err(anyhow!(
+                        "exactReference {} has neither tag nor digest",
+                        docker_reference
+                    ));
+                }
+                exact(&reference)
+            }
+            SignedIdentity::ExactRepository { docker_repository } => {
+                Identity::Repository(Reference::parse(docker_repository).name())
+            }
+            SignedIdentity::RemapIdentity {} => {
+                return Err(anyhow!("signedIdentity remapIdentity is not supported"))
+            }
+        })
+    }
+}
+
+impl Requirement {
+    fn name(&self) -> &'static str {
+        match self {
+            Requirement::InsecureAcceptAnything => "insecureAcceptAnything",
+            Requirement::Reject => "reject",
+            Requirement::SignedBy {} => "signedBy",
+            Requirement::SigstoreSigned { .. } => "sigstoreSigned",
+        }
+    }
+}
+
+/// Decision records the policy scope that applies to an image and whether
+/// the image was accepted
+#[derive(Debug, Clone, Serialize)]
+pub struct Decision {
+    // Policy file the decision was made with
+    pub policy: PathBuf,
+    // Most specific scope of the policy matching the image
+    pub scope: String,
+    // Types of the requirements of the scope
+    pub requirements: Vec<&'static str>,
+    pub accepted: bool,
+}
+
+/// Scope is the set of requirements that applies to an image
+#[derive(Debug)]
+pub struct Scope {
+    pub decision: Decision,
+    requirements: Vec<Requirement>,
+    // Image the scope applies to, as requested
+    image: Reference,
+}
+
+impl Policy {
+    /// Loads the policy at the provided path or, when none is given, the one
+    /// of the user or the system-wide one. Returns None if no policy exists.
+    pub fn find(path: Option<&Path>) -> Result<Option<(PathBuf, Policy)>> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let user = xdg::BaseDirectories::with_prefix("containers")
+                    .ok()
+                    .and_then(|dirs| dirs.find_config_file("policy.json"));
+                match user {
+                    Some(path) => path,
+                    None if Path::new(SYSTEM_POLICY).exists() => PathBuf::from(SYSTEM_POLICY),
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        let policy = match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .map_err(|e| anyhow!("invalid policy {}: {}", path.display(), e))?,
+            Err(e) => return Err(anyhow!("cannot read policy {}: {}", path.display(), e)),
+        };
+        Ok(Some((path, policy)))
+    }
+
+    /// Returns the most specific scope of the policy matching image, with
+    /// the same precedence as podman: the exact reference, the repository,
+    /// its namespaces, the registry, wildcard subdomains, the transport
+    /// default and finally the global default.
+    pub fn scope(&self, path: &Path, image: &str) -> Scope {
+        let reference = Reference::parse(image);
+        let (name, tagged) = (reference.name(), reference.qualify().to_string());
+        let scopes = self.transports.get(DOCKER_TRANSPORT);
+
+        let (scope, requirements) = match scopes.and_then(|scopes| {
+            candidates(&name, &tagged)
+                .into_iter()
+                .find_map(|scope| scopes.get(&scope).map(|requirements| (scope, requirements)))
+        }) {
+            Some((scope, requirements)) => (scope, requirements),
+            None => ("default".to_string(), &self.default),
+        };
+
+        Scope {
+            decision: Decision {
+                policy: path.to_path_buf(),
+                scope,
+                requirements: requirements.iter().map(Requirement::name).collect(),
+                accepted: false,
+            },
+            requirements: requirements.clone(),
+            image: reference,
+        }
+    }
+}
+
+impl Scope {
+    /// Checks the requirements that can be decided before the image is
+    /// pulled, refusing images the policy rejects.
+    pub fn admit(&self) -> Result<()> {
+        if self.requirements.is_empty() {
+            return Err(anyhow!(
+                "scope {} of the policy has no requirements",
+                self.decision.scope
+            ));
+        }
+        for requirement in &self.requirements {
+            match requirement {
+                Requirement::Reject => {
+                    return Err(anyhow!(
+                        "images of {} are rejected by the policy",
+                        self.decision.scope
+                    ))
+                }
+                Requirement::SignedBy {} => {
+                    return Err(anyhow!(
+                        "scope {} requires GPG signatures (signedBy), which cannot be verified",
+                        self.decision.scope
+                    ))
+                }
+                Requirement::SigstoreSigned {
+                    key_path: None,
+                    key_data: None,
+                    ..
+                } => {
+                    return Err(anyhow!(
+                        "scope {} requires keyless sigstore signatures, which cannot be verified",
+                        self.decision.scope
+                    ))
+                }
+                Requirement::SigstoreSigned {
+                    signed_identity: Some(signed_identity),
+                    ..
+                } => {
+                    if let Err(e) = signed_identity.identity(&self.image) {
+                        return Err(anyhow!("scope {}: {}", self.decision.scope, e));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies the signatures the requirements ask for, once the digest of
+    /// the pulled image is known. The signed identity is the one of the image
+    /// the scope was found for, even when the image is pulled from a mirror.
+    ///
+    /// # Arguments
+    ///
+    /// * `reference` - Repository digest of the image, such as `repo@sha256:...`, if it has one, which the signatures are read from
+    /// * `username` - Username used for signing into the registry, if not empty
+    /// * `password` - Password used for signing into the registry
+    pub async fn verify(
+        &mut self,
+        reference: Option<&str>,
+        username: &str,
+        password: &str,
+    ) -> Result<()> {
+        for requirement in &self.requirements {
+            let (pem, signed_identity) = match requirement {
+                Requirement::SigstoreSigned {
+                    key_path: Some(path),
+                    signed_identity,
+                    ..
+                } => (
+                    fs::read_to_string(path)
+                        .map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?,
+                    signed_identity,
+                ),
+                Requirement::SigstoreSigned {
+                    key_data: Some(data),
+                    signed_identity,
+                    ..
+                } => (
+                    String::from_utf8(base64::engine::general_purpose::STANDARD.decode(data)?)?,
+                    signed_identity,
+                ),
+                _ => continue,
+            };
+            let identity = signed_identity
+                .as_ref()
+                .unwrap_or(&SignedIdentity::MatchRepoDigestOrExact)
+                .identity(&self.image)?;
+            let reference = match reference {
+                Some(reference) => reference,
+                None => {
+                    return Err(anyhow!(
+                        "the image has no repository digest, its signature cannot be verified"
+                    ))
+                }
+            };
+            let key = signature::parse_key(&pem)?;
+            signature::verify_key(reference, &key, &identity, None, username, password).await?;
+        }
+
+        self.decision.accepted = true;
+        Ok(())
+    }
+}
+
+// candidates lists the scopes matching an image, most specific first
+fn candidates(name: &str, tagged: &str) -> Vec<String> {
+    let mut candidates = vec![tagged.to_string()];
+    let mut scope = name;
+    loop {
+        candidates.push(scope.to_string());
+        match scope.rsplit_once('/') {
+            Some((parent, _)) => scope = parent,
+            None => break,
+        }
+    }
+
+    // Wildcards only match subdomains of the registry host
+    let host = scope.split(':').next().unwrap_or(scope);
+    let mut domain = host;
+    while let Some((_, parent)) = domain.split_once('.') {
+        candidates.push(format!("*.{}", parent));
+        domain = parent;
+    }
+
+    candidates.push(String::new());
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{candidates, Policy, SignedIdentity};
+    use crate::reference::Reference;
+    use crate::signature::Identity;
+    use std::path::Path;
+
+    #[test]
+    fn test_candidates() {
+        assert_eq!(
+            candidates("quay.io/tflannag/bundles", "quay.io/tflannag/bundles:v1"),
+            vec![
+                "quay.io/tflannag/bundles:v1",
+                "quay.io/tflannag/bundles",
+                "quay.io/tflannag",
+                "quay.io",
+                "*.io",
+                ""
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scope() {
+        let policy: Policy = serde_json::from_str(
+            r#"{
+                "default": [{"type": "reject"}],
+                "transports": {
+                    "docker": {
+                        "quay.io/tflannag": [{"type": "insecureAcceptAnything"}],
+                        "quay.io/tflannag/private": [{"type": "signedBy", "keyType": "GPGKeys", "keyPath": "/key.gpg"}],
+                        "*.example.com": [{"type": "sigstoreSigned", "keyPath": "/cosign.pub"}]
+                    },
+                    "docker-daemon": {"": [{"type": "insecureAcceptAnything"}]}
+                }
+            }"#,
+        )
+        .unwrap();
+        let path = Path::new("policy.json");
+
+        let scope = policy.scope(path, "quay.io/tflannag/bundles:v1");
+        assert_eq!(scope.decision.scope, "quay.io/tflannag");
+        assert!(scope.admit().is_ok());
+
+        let scope = policy.scope(path, "quay.io/tflannag/private@sha256:1234");
+        assert_eq!(scope.decision.requirements, vec!["signedBy"]);
+        assert!(scope.admit().is_err());
+
+        let scope = policy.scope(path, "registry.example.com/bundles");
+        assert_eq!(scope.decision.scope, "*.example.com");
+        assert!(scope.admit().is_ok());
+
+        let scope = policy.scope(path, "alpine");
+        assert_eq!(scope.decision.scope, "default");
+        assert!(scope.admit().is_err());
+    }
+
+    #[test]
+    fn test_signed_identity() {
+        let tagged = Reference::parse("quay.io/tflannag/bundles:v1");
+        let pinned = Reference::parse("quay.io/tflannag/bundles@sha256:1234");
+        let identity = |json: &str, image: &Reference| {
+            serde_json::from_str::<SignedIdentity>(json)
+                .unwrap()
+                .identity(image)
+        };
+
+        let default = r#"{"type": "matchRepoDigestOrExact"}"#;
+        assert_eq!(
+            identity(default, &tagged).unwrap(),
+            Identity::Exact("quay.io/tflannag/bundles:v1".to_string())
+        );
+        assert_eq!(
+            identity(default, &pinned).unwrap(),
+            Identity::Repository("quay.io/tflannag/bundles".to_string())
+        );
+        assert_eq!(
+            identity(
+                r#"{"type": "exactRepository", "dockerRepository": "quay.io/tflannag/signed"}"#,
+                &tagged
+            )
+            .unwrap(),
+            Identity::Repository("quay.io/tflannag/signed".to_string())
+        );
+        assert!(identity(
+            r#"{"type": "exactReference", "dockerReference": "quay.io/tflannag/bundles"}"#,
+            &tagged
+        )
+        .is_err());
+        assert!(identity(
+            r#"{"type": "remapIdentity", "prefix": "quay.io", "signedPrefix": "example.com"}"#,
+            &tagged
+        )
+        .is_err());
+        assert!(serde_json::from_str::<SignedIdentity>(r#"{"type": "matchAnything"}"#).is_err());
+
+        let policy: Policy = serde_json::from_str(
+            r#"{"default": [{"type": "sigstoreSigned", "keyPath": "/cosign.pub", "signedIdentity": {"type": "remapIdentity", "prefix": "quay.io", "signedPrefix": "example.com"}}]}"#,
+        )
+        .unwrap();
+        let scope = policy.scope(Path::new("policy.json"), "quay.io/tflannag/bundles:v1");
+        assert!(scope.admit().is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressFinish, ProgressStyle};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::logging;
+
+// Bars of every image processed by the run, drawn on stderr. Unset when
+// progress is not displayed.
+static BARS: OnceLock<MultiProgress> = OnceLock::new();
+
+// How often spinners are redrawn
+const TICK: Duration = Duration::from_millis(100);
+
+/// Turns on the progress display, unless quiet is requested or stderr is not
+/// a terminal, such as in CI logs
+///
+/// # Arguments
+///
+/// * `quiet` - Whether the progress display was turned off with `--quiet`
+pub fn init(quiet: bool) {
+    if quiet || !std::io::stderr().is_terminal() {
+        return;
+    }
+    let _ = BARS.set(MultiProgress::with_draw_target(ProgressDrawTarget::stderr()));
+}
+
+/// Runs f with the bars hidden, so that the lines it prints are not drawn
+/// over by the bars
+pub fn suspend<F: FnOnce() -> R, R>(f: F) -> R {
+    match BARS.get() {
+        Some(bars) => bars.suspend(f),
+        None => f(),
+    }
+}
+
+// add adds a bar with the provided style, prefixed with the tag of the image
+// being processed, or a hidden bar when progress is not displayed. Bars are
+// cleared when dropped, including when the step they show fails.
+fn add(bar: ProgressBar, template: &str) -> ProgressBar {
+    let bars = match BARS.get() {
+        Some(bars) => bars,
+        None => return ProgressBar::hidden(),
+    };
+    let style = ProgressStyle::with_template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ");
+    let bar = bars.add(bar.with_style(style).with_finish(ProgressFinish::AndClear));
+    if let Some(tag) = logging::tag() {
+        bar.set_prefix(format!("[{}] ", tag));
+    }
+    bar
+}
+
+/// Returns a counter of the bytes streamed out of the runtime
+pub fn bytes(message: &str) -> ProgressBar {
+    let bar = add(
+        ProgressBar::new_spinner(),
+        "{prefix}{spinner} {msg}: {bytes} ({bytes_per_sec})",
+    );
+    bar.set_message(message.to_string());
+    bar.enable_steady_tick(TICK);
+    bar
+}
+
+/// Returns a counter of the files written locally
+pub fn files(message: &str) -> ProgressBar {
+    let bar = add(
+        ProgressBar::new_spinner(),
+        "{prefix}{spinner} {msg}: {pos} files",
+    );
+    bar.set_message(message.to_string());
+    bar.enable_steady_tick(TICK);
+    bar
+}
+
+/// Layers shows a bar per layer of an image being pulled, updated from the
+/// progress events of the runtime
+#[derive(Default)]
+pub struct Layers {
+    bars: HashMap<String, ProgressBar>,
+}
+
+impl Layers {
+    /// Updates the bar of a layer
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Short id of the layer
+    /// * `status` - What the runtime is doing with the layer, such as `Downloading`
+    /// * `current` - Bytes of the layer processed so far, if known
+    /// * `total` - Size of the layer, if known
+    pub fn update(&mut self, id: &str, status: &str, current: Option<u64>, total: Option<u64>) {
+        let bar = self.bars.entry(id.to_string()).or_insert_with(|| {
+            let bar = add(
+                ProgressBar::new(0),
+                "{prefix}{msg:<30} [{bar:30}] {bytes}/{total_bytes}",
+            );
+            bar.set_message(id.to_string());
+            bar
+        });
+        if let Some(total) = total {
+            bar.set_length(total);
+        }
+        if let Some(current) = current {
+            bar.set_position(current);
+        }
+        bar.set_message(format!("{} {}", id, status.to_lowercase()));
+    }
+
+    /// Shows what the runtime is doing with a layer, for runtimes that do not
+    /// report how much of it was processed
+    pub fn status(&mut self, id: &str, status: &str) {
+        let bar = self.bars.entry(id.to_string()).or_insert_with(|| {
+            let bar = add(ProgressBar::new_spinner(), "{prefix}{spinner} {msg}");
+            bar.enable_steady_tick(TICK);
+            bar
+        });
+        bar.set_message(format!("{} {}", id, status.to_lowercase()));
+    }
+
+    /// Clears the bars once the image is pulled
+    pub fn finish(self) {
+        for bar in self.bars.values() {
+            bar.finish_and_clear();
+        }
+    }
+}
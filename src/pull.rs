@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+
+use crate::signature::Registry;
+
+/// Pull policies accepted by `--pull`
+pub const POLICIES: &[&str] = &["always", "missing", "never", "newer"];
+
+// Media types accepted for the manifest of a tag, including the indexes of
+// multi-platform images
+const TAG_MANIFEST_TYPES: &str = "application/vnd.oci.image.index.v1+json, application/vnd.docker.distribution.manifest.list.v2+json, application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+// Header of the registry API carrying the digest of a manifest
+const DIGEST_HEADER: &str = "Docker-Content-Digest";
+
+/// Policy decides when the image is pulled.
+///
+/// * `Always` - Pull the image even if it is present locally.
+/// * `Missing` - Only pull the image when it is not present locally.
+/// * `Never` - Never pull the image, fail if it is not present locally.
+/// * `Newer` - Pull the image when it is not present locally, or when the registry serves
+///   another image for its tag than the one present locally.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Always,
+    #[default]
+    Missing,
+    Never,
+    Newer,
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "always" => Ok(Policy::Always),
+            "missing" => Ok(Policy::Missing),
+            "never" => Ok(Policy::Never),
+            "newer" => Ok(Policy::Newer),
+            _ => Err(anyhow!("unsupported pull policy {}", s)),
+        }
+    }
+}
+
+/// Returns the digest of the manifest the registry currently serves for the
+/// tag, such as `sha256:...`, as recorded in the repository digest of the
+/// images pulled from it. Only the headers of the manifest are requested,
+/// which registries such as Docker Hub do not count as a pull.
+///
+/// # Arguments
+///
+/// * `repo` - Repository of the image, such as `quay.io/tflannag/bundles`
+/// * `tag` - Tag of the image
+/// * `username` - Username used for signing into the registry, if not empty
+/// * `password` - Password used for signing into the registry
+pub async fn remote_digest(
+    repo: &str,
+    tag: &str,
+    username: &str,
+    password: &str,
+) -> Result<String> {
+    let mut registry = Registry::new(repo, username, password)?;
+    let headers = registry
+        .head(&format!("manifests/{}", tag), Some(TAG_MANIFEST_TYPES))
+        .await?;
+    match headers.get(DIGEST_HEADER) {
+        Some(digest) => Ok(digest.to_str()?.to_string()),
+        None => Err(anyhow!("the registry returned no {} header", DIGEST_HEADER)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{remote_digest, Policy};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("newer".parse::<Policy>().unwrap(), Policy::Newer);
+        assert!("sometimes".parse::<Policy>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_remote_digest() {
+        // Serve the digest of the manifest from a local registry, only for HEAD requests
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let n = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..n]).to_string();
+                let head = if request.starts_with("HEAD /v2/bundles/manifests/v1 ") {
+                    "HTTP/1.1 200 OK\r\nDocker-Content-Digest: sha256:1234\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                } else {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                };
+                stream.write_all(head.as_bytes()).await.unwrap();
+            }
+        });
+
+        let repo = format!("{}/bundles", addr);
+        assert_eq!(
+            remote_digest(&repo, "v1", "", "").await.unwrap(),
+            "sha256:1234"
+        );
+        assert!(remote_digest(&repo, "v2", "", "").await.is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+
+// Registry of the images named without a registry host
+const DOCKER_HUB: &str = "docker.io";
+
+/// Reference is an image reference split into its parts, following the
+/// conventions of the docker CLI, such as `quay.io/tflannag/bundles:v1`,
+/// `localhost:5000/bundles` or `alpine@sha256:...`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    // Repository as written in the reference, such as alpine
+    pub repo: String,
+    pub tag: Option<String>,
+    // Digest pinning the image, such as sha256:...
+    pub digest: Option<String>,
+}
+
+impl Reference {
+    /// Parses an image reference
+    pub fn parse(image: &str) -> Reference {
+        let (reference, digest) = match image.split_once('@') {
+            Some((reference, digest)) => (reference, Some(digest.to_string())),
+            None => (image, None),
+        };
+        // The port of the registry host is not a tag
+        let (repo, tag) = match reference.rsplit_once(':') {
+            Some((repo, tag)) if !tag.contains('/') => (repo, Some(tag.to_string())),
+            _ => (reference, None),
+        };
+        Reference {
+            repo: repo.to_string(),
+            tag,
+            digest,
+        }
+    }
+
+    /// Returns the tag of the reference, `latest` when it has none
+    pub fn tag_or_latest(&self) -> &str {
+        self.tag.as_deref().unwrap_or("latest")
+    }
+
+    /// Returns the last component of the repository, such as `bundles`
+    pub fn short_name(&self) -> &str {
+        self.repo.rsplit('/').next().unwrap_or(&self.repo)
+    }
+
+    /// Returns the registry host of the repository, `docker.io` for Docker
+    /// Hub, and the path of the repository in that registry, with the
+    /// `library` namespace of official images.
+    pub fn domain(&self) -> (&str, String) {
+        match self.repo.split_once('/') {
+            Some(("index.docker.io", path)) => (DOCKER_HUB, path.to_string()),
+            Some((host, path))
+                if host.contains('.') || host.contains(':') || host == "localhost" =>
+            {
+                (host, path.to_string())
+            }
+            Some(_) => (DOCKER_HUB, self.repo.clone()),
+            None => (DOCKER_HUB, format!("library/{}", self.repo)),
+        }
+    }
+
+    /// Returns the fully qualified repository, such as `docker.io/library/alpine`
+    pub fn name(&self) -> String {
+        let (host, path) = self.domain();
+        format!("{}/{}", host, path)
+    }
+
+    /// Returns the reference with its fully qualified repository, tagged
+    /// `latest` when it has neither tag nor digest
+    pub fn qualify(&self) -> Reference {
+        Reference {
+            repo: self.name(),
+            tag: match (&self.tag, &self.digest) {
+                (None, None) => Some("latest".to_string()),
+                (tag, _) => tag.clone(),
+            },
+            digest: self.digest.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.repo)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Reference;
+
+    #[test]
+    fn test_parse() {
+        let reference = Reference::parse("quay.io/tflannag/bundles:resolveset-v0.0.2");
+        assert_eq!(reference.repo, "quay.io/tflannag/bundles");
+        assert_eq!(reference.tag_or_latest(), "resolveset-v0.0.2");
+        assert_eq!(reference.short_name(), "bundles");
+
+        let reference = Reference::parse("localhost:5000/bundles");
+        assert_eq!(reference.repo, "localhost:5000/bundles");
+        assert_eq!(reference.tag, None);
+        assert_eq!(reference.tag_or_latest(), "latest");
+        assert_eq!(
+            reference.domain(),
+            ("localhost:5000", "bundles".to_string())
+        );
+        assert_eq!(
+            reference.qualify().to_string(),
+            "localhost:5000/bundles:latest"
+        );
+
+        let reference = Reference::parse("alpine@sha256:145c");
+        assert_eq!(reference.digest.as_deref(), Some("sha256:145c"));
+        assert_eq!(reference.name(), "docker.io/library/alpine");
+        assert_eq!(
+            reference.qualify().to_string(),
+            "docker.io/library/alpine@sha256:145c"
+        );
+        assert_eq!(
+            Reference::parse("tyslaton/catalog").name(),
+            "docker.io/tyslaton/catalog"
+        );
+        assert_eq!(
+            Reference::parse("index.docker.io/library/alpine").name(),
+            "docker.io/library/alpine"
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::extract::{checksum, Summary};
+use crate::policy::Decision;
+
+/// Report formats accepted by `--report`
+pub const FORMATS: &[&str] = &["json"];
+
+/// Format is the encoding of the report written at the end of a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Format::Json),
+            _ => Err(anyhow!("unsupported report format {}", s)),
+        }
+    }
+}
+
+/// Report is a machine-readable description of a run, meant to be parsed by
+/// pipelines instead of the log lines.
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    // Image reference as provided by the user
+    pub image: String,
+    // Repository digest of the image the content was copied from
+    pub digest: Option<String>,
+    // Container runtime used, `docker` or `podman`
+    pub runtime: Option<String>,
+    // Whether the image was pulled during this run
+    pub pulled: bool,
+    // Id of the container the content was copied out of
+    pub container_id: Option<String>,
+    // Trust policy decision for the image, if a policy applies
+    pub policy: Option<Decision>,
+    pub content_path: String,
+    pub download_path: String,
+    // Archive file the content was written to instead of the download path
+    pub output: Option<PathBuf>,
+    pub dry_run: bool,
+    pub success: bool,
+    pub error: Option<String>,
+    // Files written, relative to the download path, or the output archive
+    pub files: Vec<File>,
+    // Paths that already existed in the download path
+    pub conflicts: Vec<PathBuf>,
+    // Special files that were not created
+    pub skipped: Vec<PathBuf>,
+    // Paths of the image deleted by the extracted layers
+    pub whiteouts: Vec<PathBuf>,
+    pub warnings: Vec<String>,
+    pub timings: Timings,
+}
+
+/// File is a single file written by a run
+#[derive(Debug, Serialize)]
+pub struct File {
+    pub path: PathBuf,
+    // Size in bytes, unknown on a dry run
+    pub size: Option<u64>,
+    // Hex encoded SHA-256 of the content, only set for regular files
+    pub sha256: Option<String>,
+}
+
+/// Timings of the steps of a run, in milliseconds
+#[derive(Debug, Default, Serialize)]
+pub struct Timings {
+    pub pull_ms: u128,
+    pub copy_ms: u128,
+    pub total_ms: u128,
+}
+
+impl Report {
+    /// Records what the summary of a successful copy describes. Written
+    /// files are read back from disk to get their size and digest.
+    pub fn record(&mut self, summary: Summary, elapsed: Duration) -> Result<()> {
+        self.timings.copy_ms = elapsed.as_millis();
+        self.container_id = summary.container_id;
+        self.dry_run = summary.dry_run;
+        self.conflicts = summary.conflicts;
+        self.skipped = summary.skipped;
+        self.whiteouts = summary.whiteouts;
+        self.warnings = summary.warnings;
+
+        if let Some(output) = &self.output {
+            if !self.dry_run {
+                self.files.push(File::read(output, output)?);
+            }
+            return Ok(());
+        }
+
+        let root = PathBuf::from(&self.download_path);
+        for path in summary.files {
+            let file = if self.dry_run {
+                File {
+                    path,
+                    size: None,
+                    sha256: None,
+                }
+            } else {
+                File::read(&root.join(&path), &path)?
+            };
+            self.files.push(file);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the report to the provided file, or to stdout when none is set
+    pub fn write(&self, format: Format, path: Option<&Path>) -> Result<()> {
+        write(self, format, path)
+    }
+
+    /// Writes the reports of several images as a single list, in the
+    /// requested format, to the provided path or to stdout
+    pub fn write_all(reports: &[&Report], format: Format, path: Option<&Path>) -> Result<()> {
+        write(reports, format, path)
+    }
+}
+
+// write serializes the report, or list of reports, to the provided path or
+// to stdout
+fn write<T: Serialize + ?Sized>(report: &T, format: Format, path: Option<&Path>) -> Result<()> {
+    let mut report = match format {
+        Format::Json => serde_json::to_vec_pretty(report)?,
+    };
+    report.push(b'\n');
+
+    match path {
+        Some(path) => fs::write(path, report)?,
+        None => std::io::stdout().write_all(&report)?,
+    }
+    Ok(())
+}
+
+impl File {
+    // read describes the file found on disk at src, reported as path.
+    // Symlinks are not followed.
+    fn read(src: &Path, path: &Path) -> Result<File> {
+        let metadata = fs::symlink_metadata(src)?;
+        let sha256 = if metadata.is_file() {
+            Some(checksum::sha256(fs::File::open(src)?)?)
+        } else {
+            None
+        };
+        Ok(File {
+            path: path.to_path_buf(),
+            size: Some(metadata.len()),
+            sha256,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Report, Timings};
+    use crate::extract::Summary;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn test_record() {
+        let dir = std::env::temp_dir().join(format!("dcp-report-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("hello"), "hello").unwrap();
+
+        let mut report = Report {
+            download_path: dir.display().to_string(),
+            ..Default::default()
+        };
+        let summary = Summary {
+            files: vec![PathBuf::from("hello")],
+            ..Default::default()
+        };
+        report.record(summary, Duration::from_millis(5)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.files[0].size, Some(5));
+        assert_eq!(
+            report.files[0].sha256.as_deref(),
+            Some("2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824")
+        );
+        assert!(matches!(report.timings, Timings { copy_ms: 5, .. }));
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::{anyhow, Result};
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::error::Error;
+
+tokio::task_local! {
+    // When the run of the current task times out, and its `--timeout`
+    static DEADLINE: (Instant, Duration);
+}
+
+// Messages of registry errors that happen again on every attempt, even when
+// the runtime reports them as server errors
+const FATAL_MESSAGES: &[&str] = &[
+    "unauthorized",
+    "denied",
+    "authentication required",
+    "manifest unknown",
+    "not found",
+];
+
+/// Runs an operation of the runtime or of the registry, retrying it with an
+/// exponential backoff while it fails with a transient error. Fatal errors,
+/// such as a missing image or rejected credentials, are returned right away.
+///
+/// # Arguments
+///
+/// * `retries` - How many times the operation is retried
+/// * `what` - What the operation does, such as `pulling the image`
+/// * `operation` - Returns a new attempt of the operation
+pub async fn retry<T, F, Fut>(retries: u32, what: &str, mut operation: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                attempt += 1;
+                let backoff = backoff(attempt);
+                warn!(
+                    "⚠️ {} failed, retrying in {}s ({}/{}): {}",
+                    what,
+                    backoff.as_secs(),
+                    attempt,
+                    retries,
+                    e
+                );
+                tokio::time::sleep(backoff).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+// backoff returns how long to wait before an attempt, doubling from 2s up
+// to 32s
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt.min(5))
+}
+
+/// Runs a whole run, giving up on the operations waited for with [`within`]
+/// once the timeout is over.
+///
+/// # Arguments
+///
+/// * `timeout` - How long the run may take
+/// * `run` - The run
+pub async fn deadline<F: Future>(timeout: Duration, run: F) -> F::Output {
+    // A timeout too long to be represented never expires
+    match Instant::now().checked_add(timeout) {
+        Some(deadline) => DEADLINE.scope((deadline, timeout), run).await,
+        None => run.await,
+    }
+}
+
+/// Waits for an operation of the run, failing with [`Error::Timeout`] when
+/// the deadline of the run is over first. The operation is dropped, so the
+/// ones holding resources, such as a temporary container, must release them
+/// after their own `within`.
+pub async fn within<T>(operation: impl Future<Output = Result<T>>) -> Result<T> {
+    let (deadline, timeout) = match DEADLINE.try_with(|deadline| *deadline) {
+        Ok(deadline) => deadline,
+        Err(_) => return operation.await,
+    };
+    match tokio::time::timeout_at(deadline, operation).await {
+        Ok(result) => result,
+        Err(_) => {
+            let message = format!("timed out after {}s", timeout.as_secs());
+            Err(Error::Timeout(message).into())
+        }
+    }
+}
+
+/// Returns whether the error may not happen again when the operation is
+/// retried: the connection to the runtime or the registry was lost or timed
+/// out, or they answered with a server error.
+pub fn is_transient(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<docker_api::Error>() {
+            return match e {
+                docker_api::Error::Hyper(_)
+                | docker_api::Error::IO(_)
+                | docker_api::Error::ConnectionNotUpgraded => true,
+                docker_api::Error::Fault { code, message } => {
+                    transient_status(code.as_u16(), message)
+                }
+                docker_api::Error::Error(e) => transient_connection(e),
+                _ => false,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<podman_api::Error>() {
+            return match e {
+                podman_api::Error::IO(_) => true,
+                podman_api::Error::Fault { code, message } => {
+                    transient_status(code.as_u16(), message)
+                }
+                podman_api::Error::Error(e) => match e {
+                    podman_api::conn::Error::IO(_)
+                    | podman_api::conn::Error::Hyper(_)
+                    | podman_api::conn::Error::ConnectionNotUpgraded => true,
+                    podman_api::conn::Error::Fault { code, message } => {
+                        transient_status(code.as_u16(), message)
+                    }
+                    _ => false,
+                },
+                _ => false,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_timeout()
+                || e.is_connect()
+                || e.status()
+                    .is_some_and(|status| transient_status(status.as_u16(), &e.to_string()));
+        }
+        false
+    })
+}
+
+// transient_connection classifies the errors of the connection to the docker
+// socket
+fn transient_connection(e: &docker_api::conn::Error) -> bool {
+    match e {
+        docker_api::conn::Error::IO(_)
+        | docker_api::conn::Error::Hyper(_)
+        | docker_api::conn::Error::ConnectionNotUpgraded => true,
+        docker_api::conn::Error::Fault { code, message } => {
+            transient_status(code.as_u16(), message)
+        }
+        _ => false,
+    }
+}
+
+// transient_status returns whether a response with the status may succeed
+// later. Runtimes answer with a server error when the registry refuses a
+// pull, so those are told apart by their message.
+fn transient_status(status: u16, message: &str) -> bool {
+    if status != 429 && !(500..600).contains(&status) {
+        return false;
+    }
+    let message = message.to_lowercase();
+    !FATAL_MESSAGES.iter().any(|fatal| message.contains(fatal))
+}
+
+/// Parses a duration such as `30s`, `10m` or `1h`. Numbers without unit are
+/// seconds.
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let (number, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => duration.split_at(i),
+        None => (duration, "s"),
+    };
+    let number: u64 = match number.parse() {
+        Ok(number) => number,
+        Err(_) => return Err(anyhow!("{} is not a duration", duration)),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err(anyhow!("unsupported unit {} in {}", unit, duration)),
+    };
+    let seconds = match number.checked_mul(multiplier) {
+        Some(seconds) => seconds,
+        None => return Err(anyhow!("{} is too long", duration)),
+    };
+    if seconds == 0 {
+        return Err(anyhow!("{} is not a positive duration", duration));
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deadline, is_transient, parse_duration, transient_status, within};
+    use crate::error::Error;
+    use anyhow::anyhow;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_within() {
+        let slow = || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        };
+        let e = deadline(Duration::from_millis(10), within(slow()))
+            .await
+            .unwrap_err();
+        assert!(matches!(e.downcast_ref::<Error>(), Some(Error::Timeout(_))));
+
+        // Operations finishing in time, or outside of a run, are not bounded
+        let fast = deadline(Duration::from_secs(60), within(async { Ok(1) })).await;
+        assert_eq!(fast.unwrap(), 1);
+        assert_eq!(within(async { Ok(2) }).await.unwrap(), 2);
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(transient_status(503, "service unavailable"));
+        assert!(transient_status(429, "toomanyrequests"));
+        assert!(!transient_status(
+            500,
+            "unauthorized: incorrect username or password"
+        ));
+        assert!(!transient_status(404, "no such image"));
+
+        let refused = docker_api::Error::IO(std::io::ErrorKind::ConnectionRefused.into());
+        assert!(is_transient(&anyhow::Error::new(refused)));
+        let invalid = docker_api::Error::InvalidResponse("missing body".to_string());
+        assert!(!is_transient(&anyhow::Error::new(invalid)));
+        assert!(!is_transient(&anyhow!("content path not found")));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("10d").is_err());
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("9999999999999999999h").is_err());
+    }
+}
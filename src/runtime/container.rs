@@ -1,9 +1,15 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use futures_util::{pin_mut, Stream, StreamExt};
 
 use super::docker::Image as DockerImage;
 use super::podman::Image as PodmanImage;
 use super::Runtime;
+use crate::extract::limits::Limits;
+use crate::extract::{Options, Summary};
+use crate::inspect::Metadata;
+use crate::progress;
+use crate::reference::Reference;
 
 /// Container is a trait that defines the functionality of a container
 /// to be used by dcp. It contains various methods that are required for
@@ -12,14 +18,25 @@ use super::Runtime;
 ///
 /// # Functions
 ///
-/// * `pull` - Pulls the container's image. Accepts authentication and can ignore local images if `force` is set.
+/// * `pull` - Pulls the container's image. Accepts authentication and can ignore local images if `force` is set. `insecure` registries are pulled from without verifying their certificates, where the runtime allows it. Returns whether the image was pulled.
 /// * `start` - Starts the container and returns the started container's ID if successful.
 /// * `stop` - Stops the container.
-/// * `copy_files` - Copies the files from the specified locations to the specified destination locally.
+/// * `copy_files` - Copies the files from the specified locations to the specified destination locally, as configured by the extract `Options`. Starting the container and reading the files are retried up to `retries` times on transient errors, writing them never is. Returns a `Summary` of what was written.
+/// * `read_files` - Copies the files at the specified location out of the container and returns them as a tar archive, without writing anything locally.
 /// * `present_locally` - Checks to see if the image is already pulled locally.
+/// * `digest` - Returns the repository digest of the image pulled locally, if it has one.
+/// * `inspect` - Returns the configuration and manifest metadata of the image pulled locally.
+/// * `export` - Returns the image pulled locally as a docker-archive, holding its configuration and layer blobs.
+/// * `remove` - Removes the image from the local storage of the runtime.
 #[async_trait]
 pub trait Container {
-    async fn pull(&self, username: String, password: String, force: bool) -> Result<()>;
+    async fn pull(
+        &self,
+        username: String,
+        password: String,
+        force: bool,
+        insecure: bool,
+    ) -> Result<bool>;
     async fn start(&self) -> Result<String>;
     async fn stop(&self, id: String) -> Result<()>;
     async fn copy_files(
@@ -27,8 +44,15 @@ pub trait Container {
         content_path: String,
         download_path: String,
         write_to_stdout: bool,
-    ) -> Result<()>;
+        opts: &Options,
+        retries: u32,
+    ) -> Result<Summary>;
+    async fn read_files(&self, content_path: String, limits: &Limits) -> Result<Vec<u8>>;
     async fn present_locally(&self) -> bool;
+    async fn digest(&self) -> Result<Option<String>>;
+    async fn inspect(&self) -> Result<Metadata>;
+    async fn export(&self, limits: &Limits) -> Result<Vec<u8>>;
+    async fn remove(&self) -> Result<()>;
 }
 
 /// Returns a container with the provided image and runtime
@@ -73,37 +97,63 @@ pub fn new(image: String, runtime: Runtime) -> Result<Box<dyn Container>> {
     Err(anyhow!("failed to determine proper runtime for image"))
 }
 
-fn split(image: String) -> Option<(String, String)> {
-    let image_split: Vec<&str> = if image.contains('@') {
-        image.split('@').collect()
-    } else {
-        image.split(':').collect()
-    };
+/// Buffers the archive streamed out of a container by the runtime, enforcing
+/// the total size limit while the chunks are received.
+///
+/// # Arguments
+///
+/// * `stream` - Stream of archive chunks returned by the runtime
+/// * `limits` - Limits the content must stay within
+pub async fn collect<S, E>(stream: S, limits: &Limits) -> Result<Vec<u8>>
+where
+    S: Stream<Item = std::result::Result<Vec<u8>, E>>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    pin_mut!(stream);
+    let bar = progress::bytes("Streaming the content");
+    let mut bytes = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        bytes.extend_from_slice(&chunk?);
+        bar.set_position(bytes.len() as u64);
+        limits.check_stream(bytes.len())?;
+    }
+    Ok(bytes)
+}
 
-    if image_split.is_empty() {
-        return None;
+/// Picks the digest of the provided repository out of the repository digests
+/// of an image, falling back to the first one.
+///
+/// # Arguments
+///
+/// * `repo` - Repository the image was referenced with
+/// * `digests` - Repository digests of the image, such as `repo@sha256:...`
+pub fn repo_digest(repo: &str, digests: Vec<String>) -> Option<String> {
+    let prefix = format!("{}@", repo);
+    match digests.iter().find(|digest| digest.starts_with(&prefix)) {
+        Some(digest) => Some(digest.clone()),
+        None => digests.into_iter().next(),
     }
+}
 
-    let repo: String;
-    if let Some(i) = image_split.first() {
-        repo = i.to_string();
-    } else {
+// split returns the repository of an image and the tag, or the digest, it is
+// pulled with
+fn split(image: String) -> Option<(String, String)> {
+    let reference = Reference::parse(&image);
+    if reference.repo.is_empty() {
         return None;
     }
 
-    let tag: String;
-    if let Some(i) = image_split.get(1) {
-        tag = i.to_string();
-    } else {
+    let tag = match &reference.digest {
+        Some(digest) => digest.clone(),
         // Fall back to latest tag if none is provided
-        tag = String::from("latest");
-    }
-
-    Some((repo, tag))
+        None => reference.tag_or_latest().to_string(),
+    };
+    Some((reference.repo, tag))
 }
+
 #[cfg(test)]
 mod tests {
-    use super::split;
+    use super::{repo_digest, split};
 
     #[test]
     fn test_split() {
@@ -117,4 +167,21 @@ mod tests {
             "sha256:145ccb5e7e73d4ae914160c066e49f35bc2be2bb86e4ab0002a802aa436599bf".to_string()
         );
     }
+
+    #[test]
+    fn test_repo_digest() {
+        let digests = vec![
+            "quay.io/mirror/bundles@sha256:1111".to_string(),
+            "quay.io/tflannag/bundles@sha256:2222".to_string(),
+        ];
+        assert_eq!(
+            repo_digest("quay.io/tflannag/bundles", digests.clone()),
+            Some("quay.io/tflannag/bundles@sha256:2222".to_string())
+        );
+        assert_eq!(
+            repo_digest("bundles", digests),
+            Some("quay.io/mirror/bundles@sha256:1111".to_string())
+        );
+        assert_eq!(repo_digest("bundles", vec![]), None);
+    }
 }
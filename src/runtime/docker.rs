@@ -1,11 +1,17 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use docker_api::api::{ContainerCreateOpts, PullOpts, RegistryAuth, RmContainerOpts};
-use futures_util::{StreamExt, TryStreamExt};
-use std::path::PathBuf;
-use tar::Archive;
+use docker_api::api::{
+    ContainerCreateOpts, ImageBuildChunk, PullOpts, RegistryAuth, RmContainerOpts,
+};
+use futures_util::StreamExt;
+use std::path::{Path, PathBuf};
 
-use super::container::Container;
+use super::container::{collect, repo_digest, Container};
+use crate::extract::limits::Limits;
+use crate::extract::{self, Options, Summary};
+use crate::inspect::Metadata;
+use crate::progress;
+use crate::retry;
 
 pub struct Image {
     pub image: String,
@@ -18,15 +24,26 @@ pub struct Image {
 impl Container for Image {
     // pull ensures that the image is present locally and, if it is isn't
     // will do the work necessary to pull it.
-    async fn pull(&self, username: String, password: String, force: bool) -> Result<()> {
+    async fn pull(
+        &self,
+        username: String,
+        password: String,
+        force: bool,
+        insecure: bool,
+    ) -> Result<bool> {
         if self.present_locally().await {
             if !force {
                 debug!("✅ Skipping the pull process as the image was found locally");
-                return Ok(());
+                return Ok(false);
             }
             debug!("🔧 Force was set, ignoring images present locally")
         }
 
+        // Docker only skips verifying the registries its daemon is configured with
+        if insecure {
+            debug!("🔧 The registry is insecure, it must be listed in the insecure-registries of the docker daemon");
+        }
+
         let auth = RegistryAuth::builder()
             .username(username)
             .password(password)
@@ -40,19 +57,36 @@ impl Container for Image {
 
         let images = self.runtime.images();
         let mut stream = images.pull(&pull_opts);
+        let mut layers = progress::Layers::default();
         while let Some(pull_result) = stream.next().await {
             match pull_result {
+                Ok(ImageBuildChunk::PullStatus {
+                    status,
+                    id: Some(id),
+                    progress_detail,
+                    ..
+                }) => {
+                    trace!("🔧 {} {}", id, status);
+                    let detail = progress_detail.as_ref();
+                    layers.update(
+                        &id,
+                        &status,
+                        detail.and_then(|detail| detail.current),
+                        detail.and_then(|detail| detail.total),
+                    );
+                }
                 Ok(output) => {
-                    debug!("🔧 {:?}", output);
+                    trace!("🔧 {:?}", output);
                 }
                 Err(e) => {
-                    return Err(anyhow!("{}", e));
+                    return Err(e.into());
                 }
             }
         }
+        layers.finish();
 
         debug!("✅ Successfully pulled the image");
-        Ok(())
+        Ok(true)
     }
 
     // copy_files uses the image_structs values to copy files from the
@@ -62,57 +96,70 @@ impl Container for Image {
         content_path: String,
         download_path: String,
         write_to_stdout: bool,
-    ) -> Result<()> {
+        opts: &Options,
+        retries: u32,
+    ) -> Result<Summary> {
         // Create the container
-        let container_id = match self.start().await {
+        let started = retry::retry(retries, "starting the image", || self.start());
+        let container_id = match retry::within(started).await {
             Ok(id) => id,
             Err(e) => {
-                return Err(anyhow!("failed to start the image: {}", e));
+                let message = format!("failed to start the image: {}", e);
+                return Err(e.context(message));
             }
         };
 
-        let mut content_path_buffer = PathBuf::new();
-        content_path_buffer.push(&content_path);
-
-        let mut download_path_buffer = PathBuf::new();
-        download_path_buffer.push(&download_path);
+        // Copy the content out of the container, giving up when the run
+        // times out so that the container is still removed
+        let copied = retry::within(self.copy(
+            &container_id,
+            content_path,
+            download_path,
+            write_to_stdout,
+            opts,
+            retries,
+        ))
+        .await;
 
-        // Get the files from the container
-        let bytes = self
-            .runtime
-            .containers()
-            .get(&*container_id)
-            .copy_from(&content_path_buffer)
-            .try_concat()
-            .await?;
-
-        // Fail out if the buffer data processed is empty
-        if bytes.is_empty() {
-            return Err(anyhow!("failed to retrieve the files from the container"));
+        // Stop the container, even if copying the content failed
+        match self.stop(container_id.clone()).await {
+            Ok(_) => {}
+            Err(e) if copied.is_ok() => {
+                return Err(anyhow!("failed to stop the image: {}", e));
+            }
+            Err(e) => error!("❌ failed to stop the image: {}", e),
         }
 
-        // Unpack the archive
-        let mut archive = Archive::new(&bytes[..]);
-        if write_to_stdout {
-            unimplemented!()
-        } else {
-            archive.unpack(&download_path_buffer)?;
-        }
+        copied.map(|summary| Summary {
+            container_id: Some(container_id),
+            ..summary
+        })
+    }
 
-        info!(
-            "✅ Copied content to {} successfully",
-            download_path_buffer.display()
-        );
+    // read_files copies the content out of a temporary container without
+    // writing it locally.
+    async fn read_files(&self, content_path: String, limits: &Limits) -> Result<Vec<u8>> {
+        let container_id = match self.start().await {
+            Ok(id) => id,
+            // Keep the error of the runtime, to tell whether it may be retried
+            Err(e) => {
+                let message = format!("failed to start the image: {}", e);
+                return Err(e.context(message));
+            }
+        };
+
+        let read = retry::within(self.read(&container_id, Path::new(&content_path), limits)).await;
 
-        // Stop the container
+        // Stop the container, even if reading the content failed
         match self.stop(container_id).await {
             Ok(_) => {}
-            Err(e) => {
+            Err(e) if read.is_ok() => {
                 return Err(anyhow!("failed to stop the image: {}", e));
             }
+            Err(e) => error!("❌ failed to stop the image: {}", e),
         }
 
-        Ok(())
+        read
     }
 
     // start takes the the image struct's values to build a container
@@ -152,9 +199,15 @@ impl Container for Image {
         match self.runtime.images().list(&Default::default()).await {
             Ok(images) => {
                 for image in images {
-                    if let Some(repo_tag) = image.repo_tags {
-                        for tag in repo_tag {
-                            if tag == self.image {
+                    // Images referenced by digest are only listed by their repo digests
+                    let references = if self.tag.starts_with("sha256:") {
+                        image.repo_digests
+                    } else {
+                        image.repo_tags
+                    };
+                    if let Some(references) = references {
+                        for reference in references {
+                            if reference == self.image {
                                 debug!("📦 Found image {} locally", self.image);
                                 return true;
                             }
@@ -167,4 +220,97 @@ impl Container for Image {
 
         return false;
     }
+
+    // digest returns the repository digest of this container's image
+    async fn digest(&self) -> Result<Option<String>> {
+        let details = self.runtime.images().get(&self.image).inspect().await?;
+        Ok(repo_digest(&self.repo, details.repo_digests))
+    }
+
+    // inspect reads the metadata of the image from its local details
+    async fn inspect(&self) -> Result<Metadata> {
+        let details = self.runtime.images().get(&self.image).inspect().await?;
+        let config = details.config;
+        Ok(Metadata {
+            image: self.image.clone(),
+            id: details.id,
+            digest: repo_digest(&self.repo, details.repo_digests),
+            repo_tags: details.repo_tags,
+            created: Some(details.created.to_rfc3339()),
+            author: Some(details.author).filter(|author| !author.is_empty()),
+            architecture: Some(details.architecture).filter(|arch| !arch.is_empty()),
+            os: Some(details.os).filter(|os| !os.is_empty()),
+            user: Some(config.user).filter(|user| !user.is_empty()),
+            working_dir: Some(config.working_dir).filter(|dir| !dir.is_empty()),
+            entrypoint: config.entrypoint.unwrap_or_default(),
+            cmd: config.cmd.unwrap_or_default(),
+            env: config.env,
+            labels: config.labels.unwrap_or_default().into_iter().collect(),
+            layers: details.root_fs.layers.unwrap_or_default(),
+            size: u64::try_from(details.size).unwrap_or_default(),
+        })
+    }
+
+    // export saves the image the same way as `docker save`
+    async fn export(&self, limits: &Limits) -> Result<Vec<u8>> {
+        let image = self.runtime.images().get(&self.image);
+        collect(image.export(), limits).await
+    }
+
+    // remove deletes the image, which fails if a container still uses it
+    async fn remove(&self) -> Result<()> {
+        self.runtime.images().get(&self.image).delete().await?;
+        debug!("📦 Removed image {} successfully", self.image);
+        Ok(())
+    }
+}
+
+impl Image {
+    // copy streams the content out of the container with the given id and
+    // writes it locally.
+    async fn copy(
+        &self,
+        id: &str,
+        content_path: String,
+        download_path: String,
+        write_to_stdout: bool,
+        opts: &Options,
+        retries: u32,
+    ) -> Result<Summary> {
+        let mut content_path_buffer = PathBuf::new();
+        content_path_buffer.push(&content_path);
+
+        let mut download_path_buffer = PathBuf::new();
+        download_path_buffer.push(&download_path);
+
+        // Get the files from the container
+        let bytes = retry::retry(retries, "reading the image's files", || {
+            self.read(id, &content_path_buffer, &opts.limits)
+        })
+        .await?;
+
+        // Write the archive locally
+        let summary = if write_to_stdout {
+            unimplemented!()
+        } else {
+            extract::extract(&bytes, &content_path_buffer, &download_path_buffer, opts)?
+        };
+
+        Ok(summary)
+    }
+
+    // read streams the content at content_path out of the container with
+    // the given id.
+    async fn read(&self, id: &str, content_path: &Path, limits: &Limits) -> Result<Vec<u8>> {
+        let container = self.runtime.containers().get(id);
+        let stream = container.copy_from(content_path);
+        let bytes = collect(stream, limits).await?;
+
+        // Fail out if the buffer data processed is empty
+        if bytes.is_empty() {
+            return Err(anyhow!("failed to retrieve the files from the container"));
+        }
+
+        Ok(bytes)
+    }
 }
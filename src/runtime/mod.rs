@@ -3,14 +3,15 @@ mod podman;
 
 pub mod container;
 
+use anyhow::{anyhow, Result};
 use docker_api::Docker;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::OnceCell;
 
 // Imports not used by windows environments
 #[cfg(not(target_os = "windows"))]
-use anyhow::anyhow;
-#[cfg(not(target_os = "windows"))]
-use anyhow::Result;
-#[cfg(not(target_os = "windows"))]
 use podman_api::Podman;
 
 // Imports that cannot be used in windows environments
@@ -23,16 +24,59 @@ pub const DEFAULT_SOCKET: &str = "unix:///var/run/docker.sock";
 #[cfg(target_os = "windows")]
 pub const DEFAULT_SOCKET: &str = "tcp://localhost:2375";
 
+/// How long the runtime has to answer when connecting to its socket, unless
+/// set with `--connect-timeout`
+pub const DEFAULT_CONNECT_TIMEOUT: &str = "10s";
+
+#[derive(Clone)]
 pub struct Runtime {
     pub docker: Option<docker_api::Docker>,
     pub podman: Option<podman_api::Podman>,
 }
 
-pub async fn set(socket: &str) -> Option<Runtime> {
+impl Runtime {
+    /// Returns the name of the container runtime in use
+    pub fn name(&self) -> &'static str {
+        if self.podman.is_some() {
+            "podman"
+        } else {
+            "docker"
+        }
+    }
+}
+
+/// Connection connects to the container runtime the first time it is used,
+/// and is shared by every image of a run
+pub struct Connection {
+    socket: String,
+    // How long the runtime has to answer the version probe
+    connect_timeout: Duration,
+    runtime: OnceCell<Option<Runtime>>,
+}
+
+impl Connection {
+    pub fn new(socket: &str, connect_timeout: Duration) -> Self {
+        Connection {
+            socket: socket.to_string(),
+            connect_timeout,
+            runtime: OnceCell::new(),
+        }
+    }
+
+    /// Returns the runtime found at the socket, connecting to it on first use
+    pub async fn runtime(&self) -> Option<&Runtime> {
+        self.runtime
+            .get_or_init(|| set(&self.socket, self.connect_timeout))
+            .await
+            .as_ref()
+    }
+}
+
+pub async fn set(socket: &str, connect_timeout: Duration) -> Option<Runtime> {
     match Docker::new(socket) {
         Ok(docker) => {
             // Use version() as a proxy for socket connection status
-            match docker.version().await {
+            match probe(connect_timeout, docker.version()).await {
                 Ok(_) => Some(Runtime {
                     docker: Some(docker),
                     podman: None,
@@ -44,7 +88,7 @@ pub async fn set(socket: &str) -> Option<Runtime> {
                     let podman_socket = get_podman_socket(socket).ok()?;
                     match Podman::new(podman_socket) {
                         // Use version() as a proxy for socket connection status
-                        Ok(podman) => match podman.version().await {
+                        Ok(podman) => match probe(connect_timeout, podman.version()).await {
                             Ok(_) => Some(Runtime {
                                 docker: None,
                                 podman: Some(podman),
@@ -77,6 +121,19 @@ pub async fn set(socket: &str) -> Option<Runtime> {
     }
 }
 
+// probe waits for the version of the runtime, giving up when it does not
+// answer in time, such as when its socket hangs
+async fn probe<T, E: Display>(
+    timeout: Duration,
+    version: impl Future<Output = std::result::Result<T, E>>,
+) -> Result<T> {
+    match tokio::time::timeout(timeout, version).await {
+        Ok(Ok(version)) => Ok(version),
+        Ok(Err(e)) => Err(anyhow!("{}", e)),
+        Err(_) => Err(anyhow!("no answer after {}s", timeout.as_secs())),
+    }
+}
+
 #[cfg(not(target_os = "windows"))]
 fn get_podman_socket(socket: &str) -> Result<String> {
     let mut podman_socket = String::from(socket);
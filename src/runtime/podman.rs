@@ -1,11 +1,15 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use futures_util::{StreamExt, TryStreamExt};
-use podman_api::opts::{ContainerCreateOpts, PullOpts, RegistryAuth};
-use std::path::PathBuf;
-use tar::Archive;
+use futures_util::StreamExt;
+use podman_api::opts::{ContainerCreateOpts, ImageExportOpts, PullOpts, RegistryAuth};
+use std::path::{Path, PathBuf};
 
-use super::container::Container;
+use super::container::{collect, repo_digest, Container};
+use crate::extract::limits::Limits;
+use crate::extract::{self, Options, Summary};
+use crate::inspect::Metadata;
+use crate::progress;
+use crate::retry;
 
 pub struct Image {
     pub image: String,
@@ -18,11 +22,17 @@ pub struct Image {
 impl Container for Image {
     // pull ensures that the image is present locally and, if it is isn't
     // will do the work necessary to pull it.
-    async fn pull(&self, username: String, password: String, force: bool) -> Result<()> {
+    async fn pull(
+        &self,
+        username: String,
+        password: String,
+        force: bool,
+        insecure: bool,
+    ) -> Result<bool> {
         if self.present_locally().await {
             if !force {
                 debug!("✅ Skipping the pull process as the image was found locally");
-                return Ok(());
+                return Ok(false);
             }
             debug!("🔧 Force was set, ignoring images present locally")
         }
@@ -33,25 +43,35 @@ impl Container for Image {
             .build();
         let pull_opts = PullOpts::builder()
             .reference(self.image.clone().trim())
+            .tls_verify(!insecure)
             .auth(auth)
             .build();
 
         let images = self.runtime.images();
         let mut stream = images.pull(&pull_opts);
+        let mut layers = progress::Layers::default();
         while let Some(pull_result) = stream.next().await {
             match pull_result {
                 Ok(output) => {
-                    debug!("🔧 {:?}", output);
+                    trace!("🔧 {:?}", output);
+                    // Podman only reports which blob it copies, as lines such as
+                    // `Copying blob sha256:...`
+                    let line = output.stream.unwrap_or_default();
+                    if let Some(blob) = line.trim().strip_prefix("Copying blob ") {
+                        let blob = blob.trim_start_matches("sha256:");
+                        layers.status(&blob[..blob.len().min(12)], "copying");
+                    }
                 }
                 Err(e) => {
-                    return Err(anyhow!("{}", e));
+                    return Err(e.into());
                 }
             }
         }
+        layers.finish();
 
         debug!("✅ Successfully pulled the image");
 
-        Ok(())
+        Ok(true)
     }
 
     // copy_files uses the image_structs values to copy files from the
@@ -61,52 +81,70 @@ impl Container for Image {
         content_path: String,
         download_path: String,
         write_to_stdout: bool,
-    ) -> Result<()> {
+        opts: &Options,
+        retries: u32,
+    ) -> Result<Summary> {
         // Create the container
-        let container_id = match self.start().await {
+        let started = retry::retry(retries, "starting the image", || self.start());
+        let container_id = match retry::within(started).await {
             Ok(id) => id,
             Err(e) => {
-                return Err(anyhow!("failed to start the image: {}", e));
+                let message = format!("failed to start the image: {}", e);
+                return Err(e.context(message));
             }
         };
 
-        let mut content_path_buffer = PathBuf::new();
-        content_path_buffer.push(&content_path);
+        // Copy the content out of the container, giving up when the run
+        // times out so that the container is still removed
+        let copied = retry::within(self.copy(
+            &container_id,
+            content_path,
+            download_path,
+            write_to_stdout,
+            opts,
+            retries,
+        ))
+        .await;
 
-        let mut download_path_buffer = PathBuf::new();
-        download_path_buffer.push(&download_path);
+        // Stop the container, even if copying the content failed
+        match self.stop(container_id.clone()).await {
+            Ok(_) => {}
+            Err(e) if copied.is_ok() => {
+                return Err(anyhow!("failed to stop the image: {}", e));
+            }
+            Err(e) => error!("❌ failed to stop the image: {}", e),
+        }
 
-        // Get the files from the container
-        let bytes = self
-            .runtime
-            .containers()
-            .get(&*container_id)
-            .copy_from(&content_path_buffer)
-            .try_concat()
-            .await?;
+        copied.map(|summary| Summary {
+            container_id: Some(container_id),
+            ..summary
+        })
+    }
 
-        // Unpack the archive
-        let mut archive = Archive::new(&bytes[..]);
-        if write_to_stdout {
-            unimplemented!()
-        } else {
-            archive.unpack(&download_path_buffer)?;
-        }
+    // read_files copies the content out of a temporary container without
+    // writing it locally.
+    async fn read_files(&self, content_path: String, limits: &Limits) -> Result<Vec<u8>> {
+        let container_id = match self.start().await {
+            Ok(id) => id,
+            // Keep the error of the runtime, to tell whether it may be retried
+            Err(e) => {
+                let message = format!("failed to start the image: {}", e);
+                return Err(e.context(message));
+            }
+        };
 
-        info!(
-            "✅ Copied content to {} successfully",
-            download_path_buffer.display()
-        );
+        let read = retry::within(self.read(&container_id, Path::new(&content_path), limits)).await;
 
-        // Stop the container
+        // Stop the container, even if reading the content failed
         match self.stop(container_id).await {
             Ok(_) => {}
-            Err(e) => {
+            Err(e) if read.is_ok() => {
                 return Err(anyhow!("failed to stop the image: {}", e));
             }
+            Err(e) => error!("❌ failed to stop the image: {}", e),
         }
 
-        Ok(())
+        read
     }
 
     // start takes the the image struct's values to build a container
@@ -144,9 +182,15 @@ impl Container for Image {
         match self.runtime.images().list(&Default::default()).await {
             Ok(images) => {
                 for image in images {
-                    if let Some(repo_tag) = image.repo_tags {
-                        for tag in repo_tag {
-                            if tag == self.image {
+                    // Images referenced by digest are only listed by their repo digests
+                    let references = if self.tag.starts_with("sha256:") {
+                        image.repo_digests
+                    } else {
+                        image.repo_tags
+                    };
+                    if let Some(references) = references {
+                        for reference in references {
+                            if reference == self.image {
                                 debug!("📦 Found image {} locally", self.image);
                                 return true;
                             }
@@ -158,4 +202,110 @@ impl Container for Image {
         }
         false
     }
+
+    // digest returns the repository digest of this container's image
+    async fn digest(&self) -> Result<Option<String>> {
+        let data = self
+            .runtime
+            .images()
+            .get(self.image.trim())
+            .inspect()
+            .await?;
+        Ok(repo_digest(
+            &self.repo,
+            data.repo_digests.unwrap_or_default(),
+        ))
+    }
+
+    // inspect reads the metadata of the image from its local data
+    async fn inspect(&self) -> Result<Metadata> {
+        let data = self
+            .runtime
+            .images()
+            .get(self.image.trim())
+            .inspect()
+            .await?;
+        let config = data.config;
+        Ok(Metadata {
+            image: self.image.clone(),
+            id: data.id.unwrap_or_default(),
+            digest: repo_digest(&self.repo, data.repo_digests.unwrap_or_default()),
+            repo_tags: data.repo_tags.unwrap_or_default(),
+            created: data.created.map(|created| created.to_rfc3339()),
+            author: data.author.filter(|author| !author.is_empty()),
+            architecture: data.architecture.filter(|arch| !arch.is_empty()),
+            os: data.os.filter(|os| !os.is_empty()),
+            user: config.user.filter(|user| !user.is_empty()),
+            working_dir: config.working_dir.filter(|dir| !dir.is_empty()),
+            entrypoint: config.entrypoint.unwrap_or_default(),
+            cmd: config.cmd.unwrap_or_default(),
+            env: config.env.unwrap_or_default(),
+            labels: config.labels.unwrap_or_default().into_iter().collect(),
+            layers: data.root_fs.layers.unwrap_or_default(),
+            size: data
+                .size
+                .and_then(|size| u64::try_from(size).ok())
+                .unwrap_or_default(),
+        })
+    }
+
+    // export saves the image the same way as `podman save`
+    async fn export(&self, limits: &Limits) -> Result<Vec<u8>> {
+        let image = self.runtime.images().get(self.image.trim());
+        let opts = ImageExportOpts::builder().format("docker-archive").build();
+        collect(image.export(&opts), limits).await
+    }
+
+    // remove deletes the image, which fails if a container still uses it
+    async fn remove(&self) -> Result<()> {
+        let image = self.image.trim();
+        self.runtime.images().get(image).delete().await?;
+        debug!("📦 Removed image {} successfully", image);
+        Ok(())
+    }
+}
+
+impl Image {
+    // copy streams the content out of the container with the given id and
+    // writes it locally.
+    async fn copy(
+        &self,
+        id: &str,
+        content_path: String,
+        download_path: String,
+        write_to_stdout: bool,
+        opts: &Options,
+        retries: u32,
+    ) -> Result<Summary> {
+        let mut content_path_buffer = PathBuf::new();
+        content_path_buffer.push(&content_path);
+
+        let mut download_path_buffer = PathBuf::new();
+        download_path_buffer.push(&download_path);
+
+        // Get the files from the container
+        let bytes = retry::retry(retries, "reading the image's files", || {
+            self.read(id, &content_path_buffer, &opts.limits)
+        })
+        .await?;
+
+        // Write the archive locally
+        let summary = if write_to_stdout {
+            unimplemented!()
+        } else {
+            extract::extract(&bytes, &content_path_buffer, &download_path_buffer, opts)?
+        };
+
+        Ok(summary)
+    }
+
+    // read streams the content at content_path out of the container with
+    // the given id.
+    async fn read(&self, id: &str, content_path: &Path, limits: &Limits) -> Result<Vec<u8>> {
+        let container = self.runtime.containers().get(id);
+        let stream = container.copy_from(content_path);
+        let bytes = collect(stream, limits).await?;
+
+        Ok(bytes)
+    }
 }
@@ -0,0 +1,402 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::reference::Reference;
+
+/// Name of the project-local config file, looked up from the current directory upwards
+pub const PROJECT_FILE: &str = ".dcp.toml";
+
+/// Options that can be set in a config file, named like the command line flags
+pub const OPTIONS: &[&str] = &[
+    "socket",
+    "log-level",
+    "quiet",
+    "username",
+    "password",
+    "force-pull",
+    "pull",
+    "rm-image",
+    "policy",
+    "insecure-policy",
+    "download-path",
+    "content-path",
+    "strip-components",
+    "flatten",
+    "jobs",
+    "retries",
+    "timeout",
+    "connect-timeout",
+    "symlinks",
+    "on-conflict",
+    "require-empty",
+    "clean",
+    "preserve-owner",
+    "chown",
+    "no-same-permissions",
+    "strip-setuid",
+    "preserve-xattrs",
+    "mtime",
+    "special-files",
+    "max-total-size",
+    "max-file-size",
+    "max-entries",
+    "max-path-depth",
+    "checksums",
+    "checksums-style",
+    "report",
+    "verify-key",
+];
+
+// Options that are flags, which only accept booleans
+const FLAGS: &[&str] = &[
+    "quiet",
+    "force-pull",
+    "rm-image",
+    "insecure-policy",
+    "flatten",
+    "require-empty",
+    "clean",
+    "preserve-owner",
+    "no-same-permissions",
+    "strip-setuid",
+    "preserve-xattrs",
+];
+
+// Options that decide which images are trusted, which the .dcp.toml of a
+// project, found in any parent of the current directory, cannot set
+const TRUSTED: &[&str] = &["policy", "insecure-policy", "verify-key"];
+
+/// Settings are the defaults read from the config files of the user and of
+/// the project, used for the flags that are not set on the command line:
+///
+/// ```toml
+/// socket = "unix:///run/user/1000/podman/podman.sock"
+/// download-path = "out"
+///
+/// [registries."quay.io"]
+/// username = "robot"
+/// password-env = "QUAY_PASSWORD"
+/// mirror = "mirror.example.com:5000"
+///
+/// [profiles.ci]
+/// log-level = "warn"
+/// force-pull = true
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Settings {
+    // Values of the options by flag name
+    options: BTreeMap<String, String>,
+    pub registries: BTreeMap<String, Registry>,
+}
+
+/// Registry is the settings of a registry, or of a repository namespace,
+/// applied to the images pulled from it
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Registry {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // Environment variable the password is read from
+    pub password_env: Option<String>,
+    // File the password is read from
+    pub password_file: Option<PathBuf>,
+    // Pull over plain http or without verifying certificates
+    pub insecure: bool,
+    // Registry, or repository namespace, the images are pulled from instead
+    pub mirror: Option<String>,
+}
+
+// File is the content of a config file: options, registries and profiles
+// overriding them
+#[derive(Debug, Default, Deserialize)]
+struct File {
+    #[serde(flatten)]
+    base: Profile,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Profile {
+    #[serde(default)]
+    registries: BTreeMap<String, Registry>,
+    #[serde(flatten)]
+    options: BTreeMap<String, toml::Value>,
+}
+
+impl Settings {
+    /// Loads the config file of the user, `$XDG_CONFIG_HOME/dcp/config.toml`,
+    /// then the `.dcp.toml` of the project, which takes precedence. In each
+    /// file, the options of the selected profile override the other ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `profile` - Name of the profile to apply, which must be defined by one of the files
+    pub fn load(profile: Option<&str>) -> Result<Settings> {
+        let user = xdg::BaseDirectories::with_prefix("dcp")
+            .ok()
+            .and_then(|dirs| dirs.find_config_file("config.toml"));
+        let project = std::env::current_dir()
+            .ok()
+            .and_then(|dir| find_project_file(&dir));
+        Settings::read(user.as_deref(), project.as_deref(), profile)
+    }
+
+    // read merges the config files of the user and of the project, the
+    // latter taking precedence
+    fn read(
+        user: Option<&Path>,
+        project: Option<&Path>,
+        profile: Option<&str>,
+    ) -> Result<Settings> {
+        let mut settings = Settings::default();
+        let mut found = profile.is_none();
+        let paths = user
+            .map(|path| (path, false))
+            .into_iter()
+            .chain(project.map(|path| (path, true)));
+        for (path, is_project) in paths {
+            let content = fs::read_to_string(path)
+                .map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?;
+            let mut file: File = toml::from_str(&content)
+                .map_err(|e| anyhow!("invalid config file {}: {}", path.display(), e))?;
+
+            settings.merge(file.base, path, is_project)?;
+            if let Some(profile) = profile.and_then(|profile| file.profiles.remove(profile)) {
+                settings.merge(profile, path, is_project)?;
+                found = true;
+            }
+        }
+
+        if !found {
+            return Err(anyhow!(
+                "profile {} is not defined by any config file",
+                profile.unwrap_or_default()
+            ));
+        }
+        Ok(settings)
+    }
+
+    // merge overrides the settings with the ones of a profile, refusing the
+    // options deciding which images are trusted in the file of a project
+    fn merge(&mut self, profile: Profile, path: &Path, is_project: bool) -> Result<()> {
+        for (name, value) in profile.options {
+            if !OPTIONS.contains(&name.as_str()) {
+                return Err(anyhow!("unknown option {} in {}", name, path.display()));
+            }
+            if is_project && TRUSTED.contains(&name.as_str()) {
+                return Err(anyhow!(
+                    "{} cannot be set in {}, only in the config file of the user or on the command line",
+                    name,
+                    path.display()
+                ));
+            }
+            let value = match value {
+                toml::Value::Boolean(value) => value.to_string(),
+                _ if FLAGS.contains(&name.as_str()) => {
+                    return Err(anyhow!(
+                        "invalid value for {} in {}: expected a boolean",
+                        name,
+                        path.display()
+                    ))
+                }
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                _ => {
+                    return Err(anyhow!(
+                        "invalid value for {} in {}: expected a string or a number",
+                        name,
+                        path.display()
+                    ))
+                }
+            };
+            self.options.insert(name, value);
+        }
+        if is_project {
+            for (scope, registry) in &profile.registries {
+                if registry.insecure || registry.mirror.is_some() {
+                    return Err(anyhow!(
+                        "registry {} cannot be insecure or have a mirror in {}, only in the config file of the user",
+                        scope,
+                        path.display()
+                    ));
+                }
+            }
+        }
+        self.registries.extend(profile.registries);
+        Ok(())
+    }
+
+    /// Returns the value of the option, if a config file sets it
+    pub fn value_of(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    /// Returns whether a config file turns the flag on
+    pub fn is_present(&self, name: &str) -> bool {
+        self.value_of(name) == Some("true")
+    }
+}
+
+impl Registry {
+    /// Returns the username and password of the registry, if it has any
+    pub fn credentials(&self) -> Result<Option<(String, String)>> {
+        let username = match &self.username {
+            Some(username) => username.clone(),
+            None => return Ok(None),
+        };
+        let password = match (&self.password, &self.password_env, &self.password_file) {
+            (Some(password), _, _) => password.clone(),
+            (None, Some(var), _) => std::env::var(var)
+                .map_err(|e| anyhow!("cannot read the password from ${}: {}", var, e))?,
+            (None, None, Some(path)) => fs::read_to_string(path)
+                .map_err(|e| anyhow!("cannot read the password from {}: {}", path.display(), e))?
+                .trim_end()
+                .to_string(),
+            (None, None, None) => String::new(),
+        };
+        Ok(Some((username, password)))
+    }
+}
+
+/// Returns the settings of the most specific registry or repository
+/// namespace matching the image, along with the image as pulled from the
+/// mirror of those settings, if they have one.
+///
+/// # Arguments
+///
+/// * `registries` - Settings of the registries, by registry or repository namespace
+/// * `image` - Image reference as provided by the user
+pub fn find<'a>(
+    registries: &'a BTreeMap<String, Registry>,
+    image: &str,
+) -> Option<(&'a Registry, String)> {
+    let reference = Reference::parse(image);
+    let qualified = Reference {
+        repo: reference.name(),
+        ..reference
+    }
+    .to_string();
+    let (scope, registry) = registries
+        .iter()
+        .filter(|(scope, _)| {
+            qualified
+                .strip_prefix(scope.as_str())
+                .is_some_and(|rest| rest.starts_with('/'))
+        })
+        .max_by_key(|(scope, _)| scope.len())?;
+
+    let image = match &registry.mirror {
+        Some(mirror) => format!("{}{}", mirror, &qualified[scope.len()..]),
+        None => image.to_string(),
+    };
+    Some((registry, image))
+}
+
+// find_project_file looks for the project-local config file in the
+// directory and its parents
+fn find_project_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, Settings};
+    use std::fs;
+
+    #[test]
+    fn test_read() {
+        let dir = std::env::temp_dir().join(format!("dcp-settings-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("config.toml");
+        let project = dir.join(".dcp.toml");
+        fs::write(
+            &user,
+            r#"
+socket = "unix:///run/podman/podman.sock"
+log-level = "debug"
+max-entries = 1000
+
+[registries."quay.io"]
+username = "robot"
+password = "secret"
+
+[registries."quay.io/tflannag"]
+mirror = "localhost:5000/tflannag"
+
+[profiles.ci]
+log-level = "warn"
+force-pull = true
+"#,
+        )
+        .unwrap();
+        fs::write(
+            &project,
+            r#"
+download-path = "bundles"
+log-level = "info"
+
+[registries."registry.internal:5000"]
+username = "ci"
+"#,
+        )
+        .unwrap();
+        let paths = (Some(user.as_path()), Some(project.as_path()));
+
+        let settings = Settings::read(paths.0, paths.1, None).unwrap();
+        assert_eq!(settings.value_of("log-level"), Some("info"));
+        assert_eq!(settings.value_of("max-entries"), Some("1000"));
+        assert_eq!(settings.value_of("download-path"), Some("bundles"));
+        assert!(!settings.is_present("force-pull"));
+
+        // The profile of the user is overridden by the project
+        let settings = Settings::read(paths.0, paths.1, Some("ci")).unwrap();
+        assert_eq!(settings.value_of("log-level"), Some("info"));
+        assert!(settings.is_present("force-pull"));
+        assert!(Settings::read(paths.0, paths.1, Some("release")).is_err());
+
+        let (registry, image) = find(
+            &settings.registries,
+            "quay.io/tflannag/bundles:resolveset-v0.0.2",
+        )
+        .unwrap();
+        assert_eq!(image, "localhost:5000/tflannag/bundles:resolveset-v0.0.2");
+        assert!(registry.credentials().unwrap().is_none());
+        let (registry, image) = find(&settings.registries, "quay.io/tyslaton/catalog").unwrap();
+        assert_eq!(image, "quay.io/tyslaton/catalog");
+        assert_eq!(
+            registry.credentials().unwrap(),
+            Some(("robot".to_string(), "secret".to_string()))
+        );
+        assert!(find(&settings.registries, "alpine").is_none());
+
+        fs::write(&project, "force-pull = \"yes\"\n").unwrap();
+        assert!(Settings::read(paths.0, paths.1, None).is_err());
+        fs::write(&project, "image = \"alpine\"\n").unwrap();
+        assert!(Settings::read(paths.0, paths.1, None).is_err());
+
+        // Only the user can decide which images are trusted
+        for content in [
+            "insecure-policy = true\n",
+            "policy = \"policy.json\"\n",
+            "verify-key = \"cosign.pub\"\n",
+            "[profiles.ci]\nverify-key = \"cosign.pub\"\n",
+            "[registries.\"quay.io\"]\ninsecure = true\n",
+            "[registries.\"quay.io\"]\nmirror = \"localhost:5000\"\n",
+        ] {
+            fs::write(&project, content).unwrap();
+            let err = Settings::read(paths.0, paths.1, Some("ci")).unwrap_err();
+            assert!(err.to_string().contains("cannot be"), "{}", err);
+            fs::write(&user, content).unwrap();
+            assert!(Settings::read(Some(&user), None, None).is_ok());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
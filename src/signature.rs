@@ -0,0 +1,680 @@
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use p256::pkcs8::DecodePublicKey;
+use reqwest::header::{HeaderMap, ACCEPT, AUTHORIZATION, WWW_AUTHENTICATE};
+use reqwest::{Method, Response, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::extract::checksum;
+use crate::reference::Reference;
+
+// Annotation of the signature layers holding the base64 encoded signature
+const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+// Annotation naming the manifests of an OCI layout
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+// Media types accepted for the signature manifest
+const MANIFEST_TYPES: &str =
+    "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+// How long registries have to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Options configures how the signatures of an image are verified
+#[derive(Debug, Clone)]
+pub struct Options {
+    // PEM encoded public key the image must be signed with
+    pub key: PathBuf,
+    // OCI layout to read the signature from instead of the registry
+    pub layout: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct Index {
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+struct Descriptor {
+    digest: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+// Payload is the simple signing payload signed by cosign
+#[derive(Deserialize)]
+struct Payload {
+    critical: Critical,
+}
+
+#[derive(Deserialize)]
+struct Critical {
+    identity: SignedIdentity,
+    image: SignedImage,
+}
+
+#[derive(Deserialize)]
+struct SignedIdentity {
+    #[serde(rename = "docker-reference")]
+    docker_reference: String,
+}
+
+#[derive(Deserialize)]
+struct SignedImage {
+    #[serde(rename = "docker-manifest-digest")]
+    docker_manifest_digest: String,
+}
+
+/// Identity is the image a signature must claim to be made for, in the
+/// `docker-reference` of its payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identity {
+    // Any image of this fully qualified repository, such as quay.io/tflannag/bundles
+    Repository(String),
+    // Exactly this fully qualified reference, such as quay.io/tflannag/bundles:v1
+    Exact(String),
+}
+
+impl Identity {
+    // matches returns whether the docker-reference of a payload is this
+    // identity
+    fn matches(&self, signed: &str) -> bool {
+        let signed = Reference::parse(signed);
+        match self {
+            Identity::Repository(repo) => signed.name() == *repo,
+            // A reference without tag nor digest does not name a single image
+            Identity::Exact(reference) => {
+                (signed.tag.is_some() || signed.digest.is_some())
+                    && signed.qualify().to_string() == *reference
+            }
+        }
+    }
+}
+
+/// Verifies that the image with the provided repository digest carries a
+/// cosign signature made with the configured key for the repository the user
+/// asked for. The signature artifact, tagged `sha256-<digest>.sig`, is read
+/// from the registry of the repository digest, which may be a mirror, or from
+/// an OCI layout.
+///
+/// # Arguments
+///
+/// * `reference` - Repository digest of the image, such as `repo@sha256:...`
+/// * `image` - Image reference as provided by the user, which the signature must be made for
+/// * `opts` - Key and signature location
+/// * `username` - Username used for signing into the registry, if not empty
+/// * `password` - Password used for signing into the registry
+pub async fn verify(
+    reference: &str,
+    image: &str,
+    opts: &Options,
+    username: &str,
+    password: &str,
+) -> Result<()> {
+    let key = match fs::read_to_string(&opts.key) {
+        Ok(pem) => parse_key(&pem)
+            .map_err(|e| anyhow!("invalid public key {}: {}", opts.key.display(), e))?,
+        Err(e) => return Err(anyhow!("cannot read {}: {}", opts.key.display(), e)),
+    };
+    let identity = Identity::Repository(Reference::parse(image).name());
+    let layout = opts.layout.as_deref();
+    verify_key(reference, &key, &identity, layout, username, password).await
+}
+
+/// Parses a PEM encoded ECDSA P-256 public key, as written by `cosign generate-key-pair`
+pub fn parse_key(pem: &str) -> Result<VerifyingKey> {
+    Ok(VerifyingKey::from_public_key_pem(pem.trim())?)
+}
+
+/// Verifies the cosign signature of an image like `verify` does, with a key
+/// that is already parsed and the identity the signature must be made for.
+pub async fn verify_key(
+    reference: &str,
+    key: &VerifyingKey,
+    identity: &Identity,
+    layout: Option<&Path>,
+    username: &str,
+    password: &str,
+) -> Result<()> {
+    let (repo, digest) = match reference.split_once('@') {
+        Some(parts) => parts,
+        None => return Err(anyhow!("{} is not a repository digest", reference)),
+    };
+    let tag = format!("{}.sig", digest.replace(':', "-"));
+
+    let mut store = match layout {
+        Some(layout) => Store::Layout(layout.to_path_buf()),
+        None => Store::Registry(Registry::new(repo, username, password)?),
+    };
+    let manifest: Manifest = serde_json::from_slice(&store.manifest(&tag).await?)?;
+
+    let mut errors = Vec::new();
+    for layer in &manifest.layers {
+        let signature = match layer.annotations.get(SIGNATURE_ANNOTATION) {
+            Some(signature) => signature,
+            None => continue,
+        };
+        let payload = store.blob(&layer.digest).await?;
+        match check(key, signature, &payload, digest, identity) {
+            Ok(_) => {
+                debug!("✅ Verified the signature of {}", reference);
+                return Ok(());
+            }
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+
+    if errors.is_empty() {
+        return Err(anyhow!("no signature found for {}", reference));
+    }
+    Err(anyhow!(
+        "no valid signature found for {}: {}",
+        reference,
+        errors.join(", ")
+    ))
+}
+
+// check verifies a single signature of the payload and that the payload was
+// made for the image with the provided digest and identity.
+fn check(
+    key: &VerifyingKey,
+    signature: &str,
+    payload: &[u8],
+    digest: &str,
+    identity: &Identity,
+) -> Result<()> {
+    let signature = base64::engine::general_purpose::STANDARD.decode(signature)?;
+    let signature = Signature::from_der(&signature)?;
+    key.verify(payload, &signature)
+        .map_err(|_| anyhow!("signature does not match the key"))?;
+
+    let payload: Payload = serde_json::from_slice(payload)?;
+    let signed = payload.critical.image.docker_manifest_digest;
+    if signed != digest {
+        return Err(anyhow!("signature was made for another image {}", signed));
+    }
+    let signed = payload.critical.identity.docker_reference;
+    if !identity.matches(&signed) {
+        return Err(anyhow!(
+            "signature was made for another reference {}",
+            signed
+        ));
+    }
+    Ok(())
+}
+
+// Store is where signature artifacts are read from
+enum Store {
+    Registry(Registry),
+    Layout(PathBuf),
+}
+
+impl Store {
+    async fn manifest(&mut self, tag: &str) -> Result<Vec<u8>> {
+        match self {
+            Store::Registry(registry) => {
+                let path = format!("manifests/{}", tag);
+                registry.get(&path, Some(MANIFEST_TYPES)).await
+            }
+            Store::Layout(layout) => {
+                let index: Index = serde_json::from_slice(&fs::read(layout.join("index.json"))?)?;
+                match index.manifests.iter().find(|manifest| {
+                    manifest
+                        .annotations
+                        .get(REF_NAME_ANNOTATION)
+                        .map(String::as_str)
+                        == Some(tag)
+                }) {
+                    Some(manifest) => {
+                        verified(read_blob(layout, &manifest.digest)?, &manifest.digest)
+                    }
+                    None => Err(anyhow!("no signature {} in {}", tag, layout.display())),
+                }
+            }
+        }
+    }
+
+    // blob returns the content of the blob with the provided digest, after
+    // checking that it matches the digest.
+    async fn blob(&mut self, digest: &str) -> Result<Vec<u8>> {
+        let blob = match self {
+            Store::Registry(registry) => registry.get(&format!("blobs/{}", digest), None).await?,
+            Store::Layout(layout) => read_blob(layout, digest)?,
+        };
+        verified(blob, digest)
+    }
+}
+
+// verified returns the blob after checking that it matches its digest
+fn verified(blob: Vec<u8>, digest: &str) -> Result<Vec<u8>> {
+    if format!("sha256:{}", checksum::sha256(&blob[..])?) != digest {
+        return Err(anyhow!(
+            "content of blob {} does not match its digest",
+            digest
+        ));
+    }
+    Ok(blob)
+}
+
+// read_blob reads a blob of an OCI layout. The digest names the file of the
+// blob, so anything but a sha256 digest is refused.
+fn read_blob(layout: &Path, digest: &str) -> Result<Vec<u8>> {
+    match digest.split_once(':') {
+        Some(("sha256", hex))
+            if hex.len() == 64 && hex.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f')) =>
+        {
+            Ok(fs::read(layout.join("blobs").join("sha256").join(hex))?)
+        }
+        _ => Err(anyhow!("invalid digest {}", digest)),
+    }
+}
+
+// Registry is a minimal client of the OCI distribution API, able to read
+// the manifests and blobs of a single repository.
+pub(crate) struct Registry {
+    client: reqwest::Client,
+    // Scheme and host of the registry
+    url: String,
+    repository: String,
+    username: String,
+    password: String,
+    token: Option<String>,
+}
+
+impl Registry {
+    pub(crate) fn new(repo: &str, username: &str, password: &str) -> Result<Registry> {
+        let (host, repository) = split_repository(repo);
+        // Registries running locally, such as the ones used for testing, are plain http
+        let scheme = if is_local(&host) { "http" } else { "https" };
+        Ok(Registry {
+            client: reqwest::Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .build()?,
+            url: format!("{}://{}", scheme, host),
+            repository,
+            username: username.to_string(),
+            password: password.to_string(),
+            token: None,
+        })
+    }
+
+    // get reads path relative to the repository
+    async fn get(&mut self, path: &str, accept: Option<&str>) -> Result<Vec<u8>> {
+        let response = self.send(Method::GET, path, accept).await?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    // head returns the headers the registry answers for path relative to the
+    // repository, without reading its content
+    pub(crate) async fn head(&mut self, path: &str, accept: Option<&str>) -> Result<HeaderMap> {
+        let response = self.send(Method::HEAD, path, accept).await?;
+        Ok(response.headers().clone())
+    }
+
+    // send requests path relative to the repository, authenticating once if
+    // the registry asks for it.
+    async fn send(&mut self, method: Method, path: &str, accept: Option<&str>) -> Result<Response> {
+        let url = format!("{}/v2/{}/{}", self.url, self.repository, path);
+        for _ in 0..2 {
+            let mut request = self.client.request(method.clone(), &url);
+            if let Some(accept) = accept {
+                request = request.header(ACCEPT, accept);
+            }
+            if let Some(token) = &self.token {
+                request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+            }
+
+            let response = request.send().await?;
+            match response.status() {
+                StatusCode::UNAUTHORIZED if self.token.is_none() => {
+                    let challenge = match response.headers().get(WWW_AUTHENTICATE) {
+                        Some(challenge) => challenge.to_str()?.to_string(),
+                        None => return Err(anyhow!("{} requires authentication", url)),
+                    };
+                    self.authenticate(&challenge).await?;
+                }
+                status if status.is_success() => return Ok(response),
+                status => return Err(anyhow!("{} returned {}", url, status)),
+            }
+        }
+        Err(anyhow!("{} refused the credentials", url))
+    }
+
+    // authenticate requests a bearer token as described by the challenge
+    // returned by the registry.
+    async fn authenticate(&mut self, challenge: &str) -> Result<()> {
+        let params = match challenge.strip_prefix("Bearer ") {
+            Some(params) => parse_challenge(params),
+            None => {
+                return Err(anyhow!(
+                    "unsupported authentication challenge {}",
+                    challenge
+                ))
+            }
+        };
+        let realm = match params.get("realm") {
+            Some(realm) => realm,
+            None => return Err(anyhow!("authentication challenge has no realm")),
+        };
+
+        let query: Vec<(&str, &String)> = ["service", "scope"]
+            .iter()
+            .filter_map(|key| params.get(*key).map(|value| (*key, value)))
+            .collect();
+        let mut request = self.client.get(realm).query(&query);
+        if !self.username.is_empty() {
+            request = request.basic_auth(&self.username, Some(&self.password));
+        }
+
+        let response: HashMap<String, serde_json::Value> =
+            request.send().await?.error_for_status()?.json().await?;
+        let token = response
+            .get("token")
+            .or_else(|| response.get("access_token"))
+            .and_then(|token| token.as_str());
+        match token {
+            Some(token) => {
+                self.token = Some(token.to_string());
+                Ok(())
+            }
+            None => Err(anyhow!("{} returned no token", realm)),
+        }
+    }
+}
+
+// split_repository returns the host serving the registry API of a
+// repository and the path of the repository in the registry.
+fn split_repository(repo: &str) -> (String, String) {
+    match Reference::parse(repo).domain() {
+        // Docker Hub is served from another host than the one images are named after
+        ("docker.io", path) => ("registry-1.docker.io".to_string(), path),
+        (host, path) => (host.to_string(), path),
+    }
+}
+
+// is_local returns whether the registry host is the local host, with or
+// without a port
+fn is_local(host: &str) -> bool {
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if !port.is_empty() && port.bytes().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    name == "localhost" || name == "127.0.0.1"
+}
+
+// parse_challenge parses the comma separated key="value" parameters of an
+// authentication challenge. Values may contain commas.
+fn parse_challenge(params: &str) -> HashMap<String, String> {
+    let mut parsed = HashMap::new();
+    let mut rest = params.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_string();
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some((value, next)) => (value, next),
+                None => (quoted, ""),
+            },
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        parsed.insert(key, value.to_string());
+        rest = next;
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_local, parse_challenge, read_blob, split_repository, verify, verify_key, Identity,
+        Options,
+    };
+    use crate::extract::checksum;
+    use base64::Engine;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::{Signature, SigningKey};
+    use p256::pkcs8::{EncodePublicKey, LineEnding};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const DIGEST: &str = "sha256:145ccb5e7e73d4ae914160c066e49f35bc2be2bb86e4ab0002a802aa436599bf";
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32].into()).unwrap()
+    }
+
+    // sign returns the blobs of a signature artifact made with key for the
+    // image with the provided reference and digest: the manifest and the payload.
+    fn sign(key: &SigningKey, reference: &str, digest: &str) -> (Vec<u8>, Vec<u8>) {
+        let payload = format!(
+            r#"{{"critical":{{"identity":{{"docker-reference":"{}"}},"image":{{"docker-manifest-digest":"{}"}},"type":"cosign container image signature"}},"optional":null}}"#,
+            reference, digest
+        )
+        .into_bytes();
+        let signature: Signature = key.sign(&payload);
+        let signature =
+            base64::engine::general_purpose::STANDARD.encode(signature.to_der().as_bytes());
+        let manifest = format!(
+            r#"{{"schemaVersion":2,"layers":[{{"mediaType":"application/vnd.dev.cosign.simplesigning.v1+json","digest":"sha256:{}","size":{},"annotations":{{"dev.cosignproject.cosign/signature":"{}"}}}}]}}"#,
+            checksum::sha256(&payload[..]).unwrap(),
+            payload.len(),
+            signature
+        )
+        .into_bytes();
+        (manifest, payload)
+    }
+
+    // write_key writes the public key of key next to the test files
+    fn write_key(dir: &Path, key: &SigningKey) -> PathBuf {
+        let path = dir.join("cosign.pub");
+        let pem = key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        fs::write(&path, pem).unwrap();
+        path
+    }
+
+    // write_layout writes an OCI layout holding a signature artifact
+    fn write_layout(dir: &Path, manifest: &[u8], payload: &[u8]) {
+        let blobs = dir.join("blobs").join("sha256");
+        fs::create_dir_all(&blobs).unwrap();
+        let manifest_digest = checksum::sha256(manifest).unwrap();
+        fs::write(blobs.join(&manifest_digest), manifest).unwrap();
+        fs::write(blobs.join(checksum::sha256(payload).unwrap()), payload).unwrap();
+        fs::write(
+            dir.join("index.json"),
+            format!(
+                r#"{{"schemaVersion":2,"manifests":[{{"digest":"sha256:{}","annotations":{{"org.opencontainers.image.ref.name":"{}.sig"}}}}]}}"#,
+                manifest_digest,
+                DIGEST.replace(':', "-")
+            ),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verify_layout() {
+        let dir = std::env::temp_dir().join(format!("dcp-signature-{}", std::process::id()));
+        let signer = key(1);
+        let (manifest, payload) = sign(&signer, "localhost/bundles", DIGEST);
+        write_layout(&dir, &manifest, &payload);
+
+        let reference = format!("localhost/bundles@{}", DIGEST);
+        let mut opts = Options {
+            key: write_key(&dir, &signer),
+            layout: Some(dir.clone()),
+        };
+        let valid = verify(&reference, "localhost/bundles:v1", &opts, "", "").await;
+
+        opts.key = write_key(&dir, &key(2));
+        let other_key = verify(&reference, "localhost/bundles:v1", &opts, "", "").await;
+
+        let other_image = format!("localhost/bundles@sha256:{}", "0".repeat(64));
+        opts.key = write_key(&dir, &signer);
+        let unsigned = verify(&other_image, "localhost/bundles:v1", &opts, "", "").await;
+
+        let other_repository = format!("localhost/other@{}", DIGEST);
+        let other_identity = verify(&other_repository, "localhost/other", &opts, "", "").await;
+
+        // The signature is made for the image asked for, not for its mirror
+        let mirror = format!("mirror.local/bundles@{}", DIGEST);
+        let mirrored = verify(&mirror, "localhost/bundles:v1", &opts, "", "").await;
+        let mirror_identity = verify(&reference, "mirror.local/bundles", &opts, "", "").await;
+
+        let signer_key = signer.verifying_key();
+        let exact = Identity::Exact("localhost/bundles:v1".to_string());
+        let untagged = verify_key(&reference, signer_key, &exact, Some(&dir), "", "").await;
+
+        // A manifest that does not match its digest in the index is refused
+        let manifest_digest = checksum::sha256(&manifest[..]).unwrap();
+        let tampered = [&manifest[..], b"\n"].concat();
+        let blobs = dir.join("blobs").join("sha256");
+        fs::write(blobs.join(&manifest_digest), tampered).unwrap();
+        let tampered = verify(&reference, "localhost/bundles:v1", &opts, "", "").await;
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(valid.is_ok(), "{:?}", valid);
+        assert!(other_key.is_err());
+        assert!(unsigned.is_err());
+        assert!(other_identity.is_err());
+        assert!(mirrored.is_ok(), "{:?}", mirrored);
+        assert!(mirror_identity.is_err());
+        assert!(untagged.is_err());
+        assert!(tampered.is_err());
+    }
+
+    #[test]
+    fn test_identity() {
+        let repository = Identity::Repository("docker.io/library/alpine".to_string());
+        assert!(repository.matches("index.docker.io/library/alpine"));
+        assert!(repository.matches("alpine:3.18"));
+        assert!(!repository.matches("quay.io/library/alpine"));
+
+        let exact = Identity::Exact("quay.io/tflannag/bundles:v1".to_string());
+        assert!(exact.matches("quay.io/tflannag/bundles:v1"));
+        assert!(!exact.matches("quay.io/tflannag/bundles:v2"));
+        assert!(!exact.matches("quay.io/tflannag/bundles"));
+    }
+
+    #[test]
+    fn test_read_blob() {
+        let dir = std::env::temp_dir();
+        assert!(read_blob(&dir, "sha256:../../etc/passwd").is_err());
+        assert!(read_blob(&dir, &format!("sha512:{}", "0".repeat(64))).is_err());
+        assert!(read_blob(&dir, &format!("sha256:{}", "A".repeat(64))).is_err());
+        assert!(read_blob(&dir, "sha256").is_err());
+    }
+
+    #[test]
+    fn test_is_local() {
+        assert!(is_local("localhost"));
+        assert!(is_local("localhost:5000"));
+        assert!(is_local("127.0.0.1:5000"));
+        assert!(!is_local("localhost.evil.com"));
+        assert!(!is_local("127.0.0.1.nip.io:5000"));
+        assert!(!is_local("localhost:"));
+    }
+
+    #[tokio::test]
+    async fn test_verify_registry() {
+        let signer = key(3);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (manifest, payload) = sign(&signer, &format!("{}/bundles", addr), DIGEST);
+        let payload_path = format!(
+            "/v2/bundles/blobs/sha256:{}",
+            checksum::sha256(&payload[..]).unwrap()
+        );
+        let manifest_path = format!("/v2/bundles/manifests/{}.sig", DIGEST.replace(':', "-"));
+
+        // Serve the signature artifact from a local registry
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let n = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..n]).to_string();
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+                let body: &[u8] = if path == manifest_path {
+                    &manifest
+                } else if path == payload_path {
+                    &payload
+                } else {
+                    b""
+                };
+                let status = if body.is_empty() {
+                    "404 Not Found"
+                } else {
+                    "200 OK"
+                };
+                let head = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(body).await.unwrap();
+            }
+        });
+
+        let dir = std::env::temp_dir().join(format!("dcp-registry-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let opts = Options {
+            key: write_key(&dir, &signer),
+            layout: None,
+        };
+        let image = format!("{}/bundles", addr);
+        let valid = verify(&format!("{}@{}", image, DIGEST), &image, &opts, "", "").await;
+        let unsigned = verify(
+            &format!("{}@sha256:{}", image, "0".repeat(64)),
+            &image,
+            &opts,
+            "",
+            "",
+        )
+        .await;
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(valid.is_ok(), "{:?}", valid);
+        assert!(unsigned.is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            split_repository("alpine"),
+            (
+                "registry-1.docker.io".to_string(),
+                "library/alpine".to_string()
+            )
+        );
+        assert_eq!(
+            split_repository("docker.io/library/alpine"),
+            (
+                "registry-1.docker.io".to_string(),
+                "library/alpine".to_string()
+            )
+        );
+        assert_eq!(
+            split_repository("quay.io/tflannag/bundles"),
+            ("quay.io".to_string(), "tflannag/bundles".to_string())
+        );
+
+        let params = parse_challenge(
+            r#"realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/alpine:pull,push""#,
+        );
+        assert_eq!(params["realm"], "https://auth.docker.io/token");
+        assert_eq!(params["service"], "registry.docker.io");
+        assert_eq!(params["scope"], "repository:library/alpine:pull,push");
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{anyhow, Result};
+
+use crate::reference::Reference;
+
+/// Placeholders accepted in the download path and output file
+pub const PLACEHOLDERS: &[&str] = &["{name}", "{repo}", "{tag}", "{digest}"];
+
+/// Returns whether the provided path uses any placeholder
+pub fn is_template(path: &str) -> bool {
+    PLACEHOLDERS
+        .iter()
+        .any(|placeholder| path.contains(placeholder))
+}
+
+/// Replaces the placeholders of a path with the parts of the image reference:
+///
+/// * `{name}` - Last component of the repository, such as `bundles`
+/// * `{repo}` - Repository, such as `quay.io/tflannag/bundles`
+/// * `{tag}` - Tag, `latest` when the image is referenced without tag or digest
+/// * `{digest}` - Hex encoded sha256 repository digest of the image
+///
+/// # Arguments
+///
+/// * `path` - Path using placeholders, such as `out/{name}`
+/// * `image` - Image reference as provided by the user
+/// * `digest` - Repository digest of the image, such as `repo@sha256:...`, if it has one
+pub fn expand(path: &str, image: &str, digest: Option<&str>) -> Result<String> {
+    if !is_template(path) {
+        return Ok(path.to_string());
+    }
+
+    let reference = Reference::parse(image);
+    let mut expanded = path
+        .replace("{name}", reference.short_name())
+        .replace("{repo}", &reference.repo);
+    if expanded.contains("{tag}") {
+        let tag = match (&reference.tag, &reference.digest) {
+            (Some(tag), _) => tag,
+            (None, None) => "latest",
+            (None, Some(_)) => return Err(anyhow!("{} has no tag to expand {{tag}}", image)),
+        };
+        expanded = expanded.replace("{tag}", tag);
+    }
+    if expanded.contains("{digest}") {
+        let hex = match digest.and_then(|digest| digest.split_once("@sha256:")) {
+            Some((_, hex)) => hex,
+            None => {
+                return Err(anyhow!(
+                    "{} has no repository digest to expand {{digest}}",
+                    image
+                ))
+            }
+        };
+        expanded = expanded.replace("{digest}", hex);
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand;
+
+    #[test]
+    fn test_expand() {
+        let image = "quay.io/tflannag/bundles:resolveset-v0.0.2";
+        assert_eq!(
+            expand("out/{name}/{tag}", image, None).unwrap(),
+            "out/bundles/resolveset-v0.0.2"
+        );
+        assert_eq!(
+            expand("out/{repo}", "localhost:5000/bundles", None).unwrap(),
+            "out/localhost:5000/bundles"
+        );
+        assert_eq!(
+            expand("out/{name}-{tag}", "alpine", None).unwrap(),
+            "out/alpine-latest"
+        );
+        assert_eq!(
+            expand(
+                "out/{digest}",
+                image,
+                Some("quay.io/tflannag/bundles@sha256:145c")
+            )
+            .unwrap(),
+            "out/145c"
+        );
+        assert!(expand("out/{digest}", image, None).is_err());
+        assert!(expand("out/{tag}", "alpine@sha256:145c", None).is_err());
+        assert_eq!(expand("out", image, None).unwrap(), "out");
+    }
+}
@@ -186,3 +186,699 @@ fn accepts_scratch_base_images() -> TestResult {
 
     Ok(())
 }
+
+// --------------------------------------------------
+#[test]
+fn writes_output_archive() -> TestResult {
+    let path = &generate_temp_path();
+    let output = &format!("{}/bundle.tar.gz", path);
+    std::fs::create_dir_all(path)?;
+
+    // output format is inferred from the file extension
+    Command::cargo_bin(PRG)?
+        .args(["--output", output])
+        .args(["--content-path", "configs", DEFAULT_IMAGE])
+        .assert()
+        .success();
+
+    // verify that only the archive was written
+    assert!(std::path::Path::new(output).is_file());
+    assert!(!std::path::Path::new(&format!("{}/configs", path)).exists());
+
+    clean_up_test_dir(path);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_unknown_output_format() -> TestResult {
+    // the format cannot be inferred from the extension
+    Command::cargo_bin(PRG)?
+        .args(["--output", "bundle.rar", DEFAULT_IMAGE])
+        .assert()
+        .failure();
+
+    // the format must be one of the supported archive formats
+    Command::cargo_bin(PRG)?
+        .args([
+            "--output",
+            "bundle",
+            "--output-format",
+            "rar",
+            DEFAULT_IMAGE,
+        ])
+        .assert()
+        .failure();
+
+    // paths and links are written as copied to archives
+    Command::cargo_bin(PRG)?
+        .args(["--output", "bundle.tar", "--flatten", DEFAULT_IMAGE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--flatten cannot be used with --output",
+        ));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn accepts_strip_components() -> TestResult {
+    let path = &generate_temp_path();
+    let content_path = "manifests";
+
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--content-path", content_path])
+        .args(["--strip-components", "1", SCRATCH_BASE_IMAGE])
+        .assert()
+        .success();
+
+    // verify that the leading manifests directory was dropped
+    assert!(std::path::Path::new(path).exists());
+    assert!(!std::path::Path::new(&format!("{}/{}", path, content_path)).exists());
+
+    clean_up_test_dir(path);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn accepts_symlink_policy() -> TestResult {
+    let path = &generate_temp_path();
+
+    // the root filesystem of the image is extracted with absolute
+    // symlinks rewritten to stay inside the download path
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--symlinks", "rewrite-relative", DEFAULT_IMAGE])
+        .assert()
+        .success();
+
+    // unknown policies are rejected
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--symlinks", "dereference", DEFAULT_IMAGE])
+        .assert()
+        .failure();
+
+    clean_up_test_dir(path);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_on_conflict_error() -> TestResult {
+    let path = &generate_temp_path();
+    let content_path = "configs";
+
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--content-path", content_path, DEFAULT_IMAGE])
+        .assert()
+        .success();
+
+    // the content was already copied to the download path
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--on-conflict", "error"])
+        .args(["--content-path", content_path, DEFAULT_IMAGE])
+        .assert()
+        .failure();
+
+    // cleaning the download path first resolves the conflicts
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--on-conflict", "error", "--clean"])
+        .args(["--content-path", content_path, DEFAULT_IMAGE])
+        .assert()
+        .success();
+
+    clean_up_test_dir(path);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_invalid_mtime_and_owner() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--mtime", "yesterday", DEFAULT_IMAGE])
+        .assert()
+        .failure();
+
+    Command::cargo_bin(PRG)?
+        .args(["--chown", "root:root", DEFAULT_IMAGE])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_exceeding_limits() -> TestResult {
+    let path = &generate_temp_path();
+
+    // the image has more than one entry
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--max-entries", "1", DEFAULT_IMAGE])
+        .assert()
+        .code(3);
+
+    // nothing was written to the download path
+    assert!(!std::path::Path::new(path).exists());
+
+    clean_up_test_dir(path);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn accepts_dry_run() -> TestResult {
+    let path = &generate_temp_path();
+
+    // make sure the image is present locally first
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--content-path", "configs", DEFAULT_IMAGE])
+        .assert()
+        .success();
+    clean_up_test_dir(path);
+
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--dry-run", DEFAULT_IMAGE])
+        .assert()
+        .success();
+
+    // verify that nothing was written to the download path
+    assert!(!std::path::Path::new(path).exists());
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn writes_json_report() -> TestResult {
+    let path = &generate_temp_path();
+    let report = format!("{}.json", path);
+
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--content-path", "configs"])
+        .args(["--report", "json", "--report-file", &report, DEFAULT_IMAGE])
+        .assert()
+        .success();
+
+    // verify that the report lists the written files
+    let content = std::fs::read_to_string(&report)?;
+    assert!(content.contains("\"success\": true"));
+    assert!(content.contains("\"sha256\""));
+
+    clean_up_test_dir(path);
+    std::fs::remove_file(&report)?;
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn verifies_extracted_content() -> TestResult {
+    let path = &generate_temp_path();
+
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--content-path", "configs"])
+        .args(["--checksums", "sha256", DEFAULT_IMAGE])
+        .assert()
+        .success();
+    assert!(std::path::Path::new(path).join("SHA256SUMS").exists());
+
+    Command::cargo_bin(PRG)?
+        .args(["verify", "--download-path", path])
+        .args(["--content-path", "configs", DEFAULT_IMAGE])
+        .assert()
+        .success();
+
+    // verify that local edits are reported
+    std::fs::write(std::path::Path::new(path).join("added.yaml"), "edited")?;
+    Command::cargo_bin(PRG)?
+        .args(["verify", "--download-path", path])
+        .args(["--content-path", "configs", DEFAULT_IMAGE])
+        .assert()
+        .failure();
+
+    clean_up_test_dir(path);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_unexpected_digest() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--expect-digest", "sha256:1234", DEFAULT_IMAGE])
+        .assert()
+        .failure();
+
+    let path = &generate_temp_path();
+    let digest = format!("sha256:{}", "0".repeat(64));
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--expect-digest", &digest, DEFAULT_IMAGE])
+        .assert()
+        .failure();
+
+    // verify that nothing was written to the download path
+    assert!(!std::path::Path::new(path).exists());
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_unsigned_image() -> TestResult {
+    use p256::pkcs8::{EncodePublicKey, LineEnding};
+
+    let path = &generate_temp_path();
+    let layout = format!("{}-layout", path);
+    std::fs::create_dir_all(&layout)?;
+    std::fs::write(format!("{}/index.json", layout), r#"{"manifests":[]}"#)?;
+
+    // a locally generated key the image is not signed with
+    let key = format!("{}/cosign.pub", layout);
+    let pem = p256::ecdsa::SigningKey::from_bytes(&[7; 32].into())?
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)?;
+    std::fs::write(&key, pem)?;
+
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path])
+        .args(["--verify-key", &key, "--signature-layout", &layout])
+        .arg(DEFAULT_IMAGE)
+        .assert()
+        .failure();
+
+    // verify that nothing was written to the download path
+    assert!(!std::path::Path::new(path).exists());
+    std::fs::remove_dir_all(&layout)?;
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn reads_verify_key_from_config_file() -> TestResult {
+    use p256::pkcs8::{EncodePublicKey, LineEnding};
+
+    let path = &generate_temp_path();
+    std::fs::create_dir_all(path)?;
+    std::fs::write(format!("{}/index.json", path), r#"{"manifests":[]}"#)?;
+    let pem = p256::ecdsa::SigningKey::from_bytes(&[7; 32].into())?
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)?;
+    std::fs::write(format!("{}/cosign.pub", path), pem)?;
+    std::fs::create_dir_all(format!("{}/dcp", path))?;
+    std::fs::write(
+        format!("{}/dcp/config.toml", path),
+        format!(
+            "verify-key = \"{}/cosign.pub\"\ndownload-path = \"out\"\n",
+            path
+        ),
+    )?;
+
+    // the key of the config file of the user refuses the unsigned image
+    Command::cargo_bin(PRG)?
+        .current_dir(path)
+        .env("XDG_CONFIG_HOME", std::fs::canonicalize(path)?)
+        .args(["--signature-layout", path, DEFAULT_IMAGE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("signature"));
+
+    // verify that nothing was written
+    assert!(!std::path::Path::new(&format!("{}/out", path)).exists());
+    clean_up_test_dir(path);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn extracts_single_layer() -> TestResult {
+    let path = &generate_temp_path();
+
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path, "--content-path", "manifests"])
+        .args(["--layers", "..", SCRATCH_BASE_IMAGE])
+        .assert()
+        .success();
+
+    // verify that the content of the layers was extracted
+    assert!(std::path::Path::new(&format!("{}/manifests", path)).exists());
+
+    clean_up_test_dir(path);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_invalid_layer() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--layer", "latest", DEFAULT_IMAGE])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn inspects_image() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args(["inspect", "--json", SCRATCH_BASE_IMAGE])
+        .output()?;
+    assert!(output.status.success());
+
+    // verify that the metadata is printed as json on stdout
+    let metadata: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(metadata["image"], SCRATCH_BASE_IMAGE);
+    assert!(!metadata["layers"].as_array().unwrap().is_empty());
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn diffs_images() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args(["diff", "--json", "--content-path", "configs"])
+        .args([DEFAULT_IMAGE, DEFAULT_IMAGE])
+        .output()?;
+    assert!(output.status.success());
+
+    // verify that an image has no differences with itself
+    let diff: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert!(diff["added"].as_array().unwrap().is_empty());
+    assert!(diff["removed"].as_array().unwrap().is_empty());
+    assert!(diff["modified"].as_array().unwrap().is_empty());
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_rejected_by_policy() -> TestResult {
+    let path = &generate_temp_path();
+    let policy = format!("{}-policy.json", path);
+    std::fs::create_dir_all(TEST_CONTENT_DIR)?;
+    std::fs::write(
+        &policy,
+        r#"{"default": [{"type": "insecureAcceptAnything"}], "transports": {"docker": {"quay.io/tyslaton": [{"type": "reject"}]}}}"#,
+    )?;
+
+    Command::cargo_bin(PRG)?
+        .args(["--download-path", path, "--policy", &policy])
+        .arg(DEFAULT_IMAGE)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("rejected"));
+
+    // verify that nothing was written to the download path
+    assert!(!std::path::Path::new(path).exists());
+    std::fs::remove_file(&policy)?;
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_batch_with_rejected_images() -> TestResult {
+    let path = &generate_temp_path();
+    let policy = format!("{}-policy.json", path);
+    let jobs = format!("{}-jobs.yaml", path);
+    std::fs::create_dir_all(TEST_CONTENT_DIR)?;
+    std::fs::write(&policy, r#"{"default": [{"type": "reject"}]}"#)?;
+    std::fs::write(
+        &jobs,
+        format!(
+            "jobs:\n  - image: {}\n    destination: {}/a\n  - image: {}\n    destination: {}/b\n",
+            DEFAULT_IMAGE, path, SCRATCH_BASE_IMAGE, path
+        ),
+    )?;
+
+    Command::cargo_bin(PRG)?
+        .args(["batch", &jobs, "--policy", &policy, "--retries", "0"])
+        .assert()
+        .failure();
+
+    // verify that nothing was written for either image
+    assert!(!std::path::Path::new(path).exists());
+    std::fs::remove_file(&policy)?;
+    std::fs::remove_file(&jobs)?;
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_images_rejected_by_policy() -> TestResult {
+    let path = &generate_temp_path();
+    let policy = format!("{}-policy.json", path);
+    let report = format!("{}-report.json", path);
+    std::fs::create_dir_all(TEST_CONTENT_DIR)?;
+    std::fs::write(&policy, r#"{"default": [{"type": "reject"}]}"#)?;
+
+    Command::cargo_bin(PRG)?
+        .args([
+            DEFAULT_IMAGE,
+            SCRATCH_BASE_IMAGE,
+            "-d",
+            &format!("{}/{{name}}", path),
+            "--jobs",
+            "2",
+            "--policy",
+            &policy,
+            "--report",
+            "json",
+            "--report-file",
+            &report,
+        ])
+        .assert()
+        .failure();
+
+    // verify that nothing was written and both images were reported
+    assert!(!std::path::Path::new(path).exists());
+    let reports: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report)?)?;
+    assert_eq!(reports.as_array().map(Vec::len), Some(2));
+    assert_eq!(reports[0]["image"], DEFAULT_IMAGE);
+    std::fs::remove_file(&policy)?;
+    std::fs::remove_file(&report)?;
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn reads_project_config_file() -> TestResult {
+    let path = &generate_temp_path();
+    std::fs::create_dir_all(path)?;
+    std::fs::write(
+        format!("{}/policy.json", path),
+        r#"{"default": [{"type": "reject"}]}"#,
+    )?;
+    std::fs::create_dir_all(format!("{}/dcp", path))?;
+    std::fs::write(
+        format!("{}/dcp/config.toml", path),
+        "policy = \"policy.json\"\n",
+    )?;
+    std::fs::write(
+        format!("{}/.dcp.toml", path),
+        "download-path = \"out\"\n\n[profiles.ci]\nlog-level = \"warn\"\n",
+    )?;
+
+    // the policy of the user rejects the image, and the profile of the project is applied
+    Command::cargo_bin(PRG)?
+        .current_dir(path)
+        .env("XDG_CONFIG_HOME", std::fs::canonicalize(path)?)
+        .args([DEFAULT_IMAGE, "--profile", "ci"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("rejected"));
+
+    // the project cannot decide which images are trusted
+    std::fs::write(
+        format!("{}/.dcp.toml", path),
+        "insecure-policy = true\ndownload-path = \"out\"\n",
+    )?;
+    Command::cargo_bin(PRG)?
+        .current_dir(path)
+        .env("XDG_CONFIG_HOME", std::fs::canonicalize(path)?)
+        .arg(DEFAULT_IMAGE)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("insecure-policy cannot be set"));
+
+    // profiles must be defined by a config file
+    Command::cargo_bin(PRG)?
+        .current_dir(path)
+        .env("XDG_CONFIG_HOME", std::fs::canonicalize(path)?)
+        .args([DEFAULT_IMAGE, "--profile", "release"])
+        .assert()
+        .failure();
+
+    // verify that nothing was written
+    assert!(!std::path::Path::new(&format!("{}/out", path)).exists());
+    clean_up_test_dir(path);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn reads_environment_variables() -> TestResult {
+    let path = &generate_temp_path();
+    std::fs::create_dir_all(path)?;
+    std::fs::write(
+        format!("{}/reject.json", path),
+        r#"{"default": [{"type": "reject"}]}"#,
+    )?;
+    std::fs::create_dir_all(format!("{}/dcp", path))?;
+    std::fs::write(
+        format!("{}/dcp/config.toml", path),
+        "policy = \"accept.json\"\ndownload-path = \"out\"\n",
+    )?;
+
+    // the environment takes precedence over the config file of the user
+    Command::cargo_bin(PRG)?
+        .current_dir(path)
+        .env("XDG_CONFIG_HOME", std::fs::canonicalize(path)?)
+        .env("DCP_POLICY", "reject.json")
+        .arg(DEFAULT_IMAGE)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("rejected"));
+
+    // verify that nothing was written
+    assert!(!std::path::Path::new(&format!("{}/out", path)).exists());
+    clean_up_test_dir(path);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn lists_environment_variables() -> TestResult {
+    let help = Command::cargo_bin(PRG)?.arg("--help").assert().success();
+    let output = String::from_utf8(help.get_output().stdout.clone())?;
+
+    // every option of the config files can be set from the environment
+    for name in dcp::settings::OPTIONS {
+        let var = dcp::config::env_var(name);
+        assert!(output.contains(&var), "{} is not listed", var);
+    }
+    assert!(output.contains("only taken from the command line"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_invalid_pull_policy() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--pull", "sometimes", DEFAULT_IMAGE])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn accepts_quiet() -> TestResult {
+    let path = &generate_temp_path();
+    let policy = format!("{}-policy.json", path);
+    std::fs::create_dir_all(TEST_CONTENT_DIR)?;
+    std::fs::write(&policy, r#"{"default": [{"type": "reject"}]}"#)?;
+
+    // progress is turned off, the run still reports why it failed
+    Command::cargo_bin(PRG)?
+        .args(["--quiet", "--policy", &policy, "-d", path, DEFAULT_IMAGE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("rejected"));
+
+    std::fs::remove_file(&policy)?;
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_rm_image_with_inspect() -> TestResult {
+    // inspect keeps the images it pulls
+    Command::cargo_bin(PRG)?
+        .args(["inspect", "--rm-image", DEFAULT_IMAGE])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_invalid_timeout() -> TestResult {
+    for args in [["--timeout", "soon"], ["--connect-timeout", "0s"]] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .arg(DEFAULT_IMAGE)
+            .assert()
+            .failure();
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn exits_with_error_code() -> TestResult {
+    let path = &generate_temp_path();
+    let policy = format!("{}-policy.json", path);
+    std::fs::create_dir_all(TEST_CONTENT_DIR)?;
+    std::fs::write(&policy, r#"{"default": [{"type": "reject"}]}"#)?;
+
+    // invalid flags
+    Command::cargo_bin(PRG)?
+        .args(["--pull", "sometimes", DEFAULT_IMAGE])
+        .assert()
+        .code(2);
+    Command::cargo_bin(PRG)?
+        .args(["--timeout", "soon", DEFAULT_IMAGE])
+        .assert()
+        .code(2);
+
+    // no runtime answers on the socket
+    Command::cargo_bin(PRG)?
+        .args(["--socket", "unix:///nonexistent/dcp.sock"])
+        .args(["--insecure-policy", "-d", path, DEFAULT_IMAGE])
+        .assert()
+        .code(4);
+
+    // the trust policy rejects the image
+    Command::cargo_bin(PRG)?
+        .args(["--policy", &policy, "-d", path, DEFAULT_IMAGE])
+        .assert()
+        .code(9);
+
+    std::fs::remove_file(&policy)?;
+
+    Ok(())
+}